@@ -14,7 +14,5 @@ docker build -t touplitoui/wanaplay-booker-bot .
 docker push touplitoui/wanaplay-booker-bot
 docker build -f Dockerfile.api -t touplitoui/wanaplay-booker-api .
 docker push touplitoui/wanaplay-booker-api
-pushd wanabot
-docker build -f Dockerfile -t touplitoui/wanaplay-booker-chatbot .
+docker build -f Dockerfile.telegram -t touplitoui/wanaplay-booker-chatbot .
 docker push touplitoui/wanaplay-booker-chatbot
-popd
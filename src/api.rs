@@ -0,0 +1,608 @@
+use crate::clock::ClockEstimate;
+use crate::planning::{Planning, MAX_PLANNING_DAYS};
+use crate::watcher::Watcher;
+use crate::{Booking, CourtWithId, Result};
+use chrono::{DateTime, FixedOffset};
+use failure::bail;
+use schemars::gen::SchemaSettings;
+use schemars::JsonSchema;
+use serde_json::Value;
+
+pub const API_VERSION: &str = "v1";
+pub const DEFAULT_PER_PAGE: usize = 20;
+pub const MAX_PER_PAGE: usize = 100;
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[schemars(rename = "Errors")]
+pub struct ErrorContainer {
+    pub errors: Vec<String>,
+}
+
+impl ErrorContainer {
+    pub fn new(errors: Vec<String>) -> Self {
+        ErrorContainer { errors }
+    }
+}
+
+/// One page of a collection, `page` starts at 1.
+#[derive(Debug, Serialize, Clone, JsonSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    #[schemars(range(min = 1))]
+    pub page: usize,
+    #[schemars(range(min = 1, max = 100))]
+    pub per_page: usize,
+    pub total: usize,
+}
+
+impl<T> Page<T> {
+    pub fn of(items: Vec<T>, page: Option<usize>, per_page: Option<usize>) -> Result<Self> {
+        let page = page.unwrap_or(1);
+        let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if page == 0 {
+            bail!("page starts at 1");
+        }
+        if per_page == 0 || per_page > MAX_PER_PAGE {
+            bail!("per_page should be between 1 and {}", MAX_PER_PAGE);
+        }
+        let total = items.len();
+        Ok(Page {
+            items: items
+                .into_iter()
+                .skip((page - 1) * per_page)
+                .take(per_page)
+                .collect(),
+            page,
+            per_page,
+            total,
+        })
+    }
+}
+
+/// Body of `POST /bookings`, `slot_id` comes from the courts of the slot starting at
+/// `start`.
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct NewBooking {
+    pub slot_id: String,
+    pub start: DateTime<FixedOffset>,
+}
+
+fn date_schema() -> Value {
+    json!({"type": "string", "format": "date", "example": "2026-11-05"})
+}
+
+fn date_time_schema() -> Value {
+    json!({"type": "string", "format": "date-time", "example": "2026-11-05T19:20:00+01:00"})
+}
+
+/// `$ref` to the schema of `T`, `components` has to generate it.
+fn reference<T: JsonSchema>() -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", T::schema_name()) })
+}
+
+/// Schemas of the bodies, derived from their types as serde (de)serializes them.
+fn components() -> serde_json::Map<String, Value> {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    generator.subschema_for::<ErrorContainer>();
+    generator.subschema_for::<NewBooking>();
+    generator.subschema_for::<Page<Booking>>();
+    generator.subschema_for::<CourtWithId>();
+    generator.subschema_for::<Page<Watcher>>();
+    generator.subschema_for::<Planning>();
+    generator.subschema_for::<ClockEstimate>();
+    generator
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| (name, serde_json::to_value(schema).unwrap()))
+        .collect()
+}
+
+fn json_content(schema: Value) -> Value {
+    json!({"application/json": {"schema": schema}})
+}
+
+fn response(description: &str, schema: Value) -> Value {
+    json!({"description": description, "content": json_content(schema)})
+}
+
+fn errors(description: &str) -> Value {
+    response(description, reference::<ErrorContainer>())
+}
+
+fn parameter(name: &str, location: &str, schema: Value) -> Value {
+    json!({"name": name, "in": location, "required": location == "path", "schema": schema})
+}
+
+fn pagination() -> Vec<Value> {
+    vec![
+        parameter(
+            "page",
+            "query",
+            json!({"type": "integer", "minimum": 1, "default": 1}),
+        ),
+        parameter(
+            "per_page",
+            "query",
+            json!({"type": "integer", "minimum": 1, "maximum": MAX_PER_PAGE, "default": DEFAULT_PER_PAGE}),
+        ),
+    ]
+}
+
+/// Action on a bot answering the bot as it is afterwards.
+fn bot_action(summary: &str) -> Value {
+    json!({"post": {
+        "summary": summary,
+        "parameters": [parameter("name", "path", json!({"type": "string"}))],
+        "responses": {"200": response("the bot", reference::<Watcher>()), "404": errors("unknown bot")},
+    }})
+}
+
+/// OpenAPI 3 document of the proxy, served at `/api/v1/openapi.json`.
+pub fn openapi() -> Value {
+    let name = parameter("name", "path", json!({"type": "string"}));
+    let id = parameter("id", "path", json!({"type": "string"}));
+    let date = parameter("date", "path", date_schema());
+    let cancel_link = json!([
+        id.clone(),
+        parameter(
+            "expires",
+            "query",
+            json!({"type": "integer", "description": "unix timestamp"})
+        ),
+        parameter("signature", "query", json!({"type": "string"})),
+    ]);
+    json!({
+        "openapi": "3.0.3",
+        "info": {"title": "wanaplay booker", "version": API_VERSION},
+        "servers": [{"url": format!("/api/{}", API_VERSION)}],
+        "security": [{"apiKey": []}, {"bearer": []}],
+        "components": {
+            "schemas": components(),
+            "securitySchemes": {
+                "apiKey": {"type": "apiKey", "in": "header", "name": "X-Api-Key"},
+                "bearer": {"type": "http", "scheme": "bearer"},
+            },
+        },
+        "paths": {
+            "/bots": {
+                "get": {
+                    "summary": "list the bots",
+                    "parameters": pagination(),
+                    "responses": {"200": response("a page of bots", reference::<Page<Watcher>>())},
+                },
+                "post": {
+                    "summary": "create a bot",
+                    "requestBody": {"required": true, "content": json_content(reference::<Watcher>())},
+                    "responses": {
+                        "201": response("the created bot", reference::<Watcher>()),
+                        "409": errors("a bot already has this name"),
+                        "422": errors("invalid bot"),
+                    },
+                },
+            },
+            "/bots/{name}": {
+                "get": {
+                    "parameters": [name.clone()],
+                    "responses": {"200": response("the bot", reference::<Watcher>()), "404": errors("unknown bot")},
+                },
+                "put": {
+                    "parameters": [name.clone()],
+                    "requestBody": {"required": true, "content": json_content(reference::<Watcher>())},
+                    "responses": {
+                        "200": {"description": "updated"},
+                        "404": errors("unknown bot"),
+                        "409": errors("the name can't change"),
+                        "422": errors("invalid bot"),
+                    },
+                },
+                "delete": {
+                    "parameters": [name],
+                    "responses": {"204": {"description": "removed"}, "404": errors("unknown bot")},
+                },
+            },
+            "/bots/{name}/start": bot_action("start the bot"),
+            "/bots/{name}/stop": bot_action("stop the bot"),
+            "/bots/{name}/pause": bot_action("stop booking until resumed"),
+            "/bots/{name}/resume": bot_action("book again"),
+            "/bots/{name}/skip": {"post": {
+                "summary": "don't book the given dates",
+                "parameters": [
+                    parameter("name", "path", json!({"type": "string"})),
+                    parameter("date", "query", json!({"type": "string", "example": "2026-12-21..2027-01-03"})),
+                ],
+                "responses": {"200": response("the bot", reference::<Watcher>()), "404": errors("unknown bot")},
+            }},
+            "/deployments": {"post": {
+                "summary": "deploy every enabled bot",
+                "responses": {"201": {"description": "deployed"}, "500": errors("deployment failed")},
+            }},
+            "/bookings": {
+                "get": {
+                    "parameters": pagination(),
+                    "responses": {"200": response("a page of bookings", reference::<Page<Booking>>())},
+                },
+                "post": {
+                    "requestBody": {"required": true, "content": json_content(reference::<NewBooking>())},
+                    "responses": {
+                        "201": response("the court booked", reference::<Booking>()),
+                        "400": errors("start is not a court time"),
+                        "409": errors("someone else booked the court"),
+                        "502": errors("wanaplay refused the booking"),
+                    },
+                },
+            },
+            "/bookings/{id}": {"delete": {
+                "summary": "cancels the booking and answers its cancellation invite",
+                "parameters": [id.clone()],
+                "responses": {
+                    "200": {"description": "cancellation invite", "content": {"text/calendar": {}}},
+                    "404": errors("unknown booking"),
+                    "502": errors("wanaplay refused the cancellation"),
+                },
+            }},
+            "/bookings/{id}/cancel": {
+                "get": {
+                    "summary": "page confirming the cancellation of a signed reminder link, no API key needed",
+                    "security": [],
+                    "parameters": cancel_link.clone(),
+                    "responses": {
+                        "200": {"description": "confirmation form", "content": {"text/html": {}}},
+                        "403": errors("invalid or expired link"),
+                    },
+                },
+                "post": {
+                    "summary": "cancels the booking of a signed reminder link, no API key needed",
+                    "security": [],
+                    "parameters": cancel_link,
+                    "responses": {
+                        "200": {"description": "cancelled", "content": {"text/html": {}}},
+                        "403": errors("invalid or expired link"),
+                    },
+                },
+            },
+            "/bookings/{id}/invite.ics": {"get": {
+                "parameters": [id],
+                "responses": {
+                    "200": {"description": "calendar invite", "content": {"text/calendar": {}}},
+                    "404": errors("unknown booking"),
+                },
+            }},
+            "/planning": {"get": {
+                "summary": "courts of every slot of a range of days",
+                "parameters": [
+                    parameter("from", "query", json!({"type": "string", "format": "date", "description": "today by default"})),
+                    parameter("to", "query", json!({
+                        "type": "string",
+                        "format": "date",
+                        "description": format!("a week after from by default, at most {} days after", MAX_PLANNING_DAYS - 1),
+                    })),
+                ],
+                "responses": {"200": response("the planning", reference::<Planning>()), "400": errors("invalid range")},
+            }},
+            "/slots/{date}": {"get": {
+                "summary": "starts of the slots with a free court",
+                "parameters": [date],
+                "responses": {"200": response("slot starts", json!({"type": "array", "items": date_time_schema()}))},
+            }},
+            "/slots/{start}/courts": {"get": {
+                "summary": "free courts of a slot",
+                "parameters": [parameter("start", "path", date_time_schema())],
+                "responses": {"200": response("free courts", json!({"type": "array", "items": reference::<CourtWithId>()}))},
+            }},
+            "/diagnostics/clock": {"get": {
+                "summary": "offset of the wanaplay clock",
+                "responses": {"200": response("the estimate", reference::<ClockEstimate>()), "502": errors("wanaplay unreachable")},
+            }},
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::booker::{Schedule, SkipPeriod, Sniper, WatcherKind};
+    use crate::clock::ClockSample;
+    use crate::club::club_time;
+    use crate::notifier::BookingOutcome;
+    use crate::planning::{planning_day, PlanningCell};
+    use crate::watcher::WatcherStatus;
+    use chrono::{NaiveDate, NaiveTime, Utc};
+    use regex::Regex;
+    use serde::Serialize;
+
+    /// Resolves a `$ref` of the components of the document.
+    fn resolve<'a>(document: &'a Value, schema: &'a Value) -> &'a Value {
+        match schema["$ref"].as_str() {
+            Some(reference) => {
+                let name = reference.trim_start_matches("#/components/schemas/");
+                let component = &document["components"]["schemas"][name];
+                assert!(!component.is_null(), "{} isn't generated", reference);
+                resolve(document, component)
+            }
+            None => schema,
+        }
+    }
+
+    /// Properties of the schema, with the ones of every part of its `allOf`, `oneOf` or
+    /// `anyOf`.
+    fn properties(document: &Value, schema: &Value) -> Option<serde_json::Map<String, Value>> {
+        let schema = resolve(document, schema);
+        let mut found = schema["properties"].as_object().cloned();
+        for keyword in &["allOf", "oneOf", "anyOf"] {
+            if let Value::Array(parts) = &schema[*keyword] {
+                let parts = parts.iter().filter_map(|part| properties(document, part));
+                found
+                    .get_or_insert_with(Default::default)
+                    .extend(parts.flatten());
+            }
+        }
+        found
+    }
+
+    /// Problems of `value` against `schema`, only the keywords the document uses. Objects
+    /// can't have properties missing from their schema when `closed`, that's how fields
+    /// drift.
+    fn violations(
+        document: &Value,
+        schema: &Value,
+        value: &Value,
+        path: &str,
+        closed: bool,
+    ) -> Vec<String> {
+        let schema = resolve(document, schema);
+        let mut found = vec![];
+        if let Value::Array(parts) = &schema["allOf"] {
+            // the other parts hold the other properties
+            for part in parts {
+                found.extend(violations(document, part, value, path, false));
+            }
+        }
+        if let Value::Array(variants) = &schema["oneOf"] {
+            let matching = variants
+                .iter()
+                .filter(|variant| violations(document, variant, value, path, closed).is_empty())
+                .count();
+            if matching != 1 {
+                found.push(format!("{}: {} matches {} variants", path, value, matching));
+            }
+        }
+        if let Value::Array(variants) = &schema["anyOf"] {
+            // the schema itself holds the other properties
+            if !variants
+                .iter()
+                .any(|variant| violations(document, variant, value, path, false).is_empty())
+            {
+                found.push(format!("{}: {} matches none of the variants", path, value));
+            }
+        }
+        let type_matches = match schema["type"].as_str() {
+            Some("string") => value.is_string(),
+            Some("integer") => value.is_i64() || value.is_u64(),
+            Some("boolean") => value.is_boolean(),
+            Some("array") => value.is_array(),
+            Some("object") => value.is_object(),
+            _ => true,
+        };
+        if !type_matches {
+            found.push(format!("{}: {} is not a {}", path, value, schema["type"]));
+            return found;
+        }
+        if let Value::Array(allowed) = &schema["enum"] {
+            if !allowed.contains(value) {
+                found.push(format!("{}: {} is not one of {:?}", path, value, allowed));
+            }
+        }
+        if let (Some(pattern), Some(string)) = (schema["pattern"].as_str(), value.as_str()) {
+            if !Regex::new(pattern).unwrap().is_match(string) {
+                found.push(format!("{}: {} doesn't match {}", path, string, pattern));
+            }
+        }
+        if let (Some(format), Some(string)) = (schema["format"].as_str(), value.as_str()) {
+            let valid = match format {
+                "date" => string.parse::<NaiveDate>().is_ok(),
+                "date-time" => DateTime::parse_from_rfc3339(string).is_ok(),
+                _ => true,
+            };
+            if !valid {
+                found.push(format!("{}: {} is not a {}", path, string, format));
+            }
+        }
+        if let (Some(items), Value::Array(values)) = (schema.get("items"), value) {
+            for (index, item) in values.iter().enumerate() {
+                found.extend(violations(
+                    document,
+                    items,
+                    item,
+                    &format!("{}[{}]", path, index),
+                    true,
+                ));
+            }
+        }
+        if let Value::Object(fields) = value {
+            if let Value::Array(required) = &schema["required"] {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !fields.contains_key(name) {
+                        found.push(format!("{}: {} is missing", path, name));
+                    }
+                }
+            }
+            let documented = properties(document, schema);
+            for (name, field) in fields {
+                if let Some(property) = schema["properties"].get(name) {
+                    found.extend(violations(
+                        document,
+                        property,
+                        field,
+                        &format!("{}.{}", path, name),
+                        true,
+                    ));
+                }
+                let undocumented = match &documented {
+                    Some(documented) => !documented.contains_key(name),
+                    None => false,
+                };
+                if closed && undocumented {
+                    found.push(format!("{}: {} isn't documented", path, name));
+                }
+            }
+        }
+        found
+    }
+
+    fn assert_valid<T: JsonSchema + Serialize>(values: &[T]) {
+        let document = openapi();
+        for value in values {
+            let value = serde_json::to_value(value).unwrap();
+            let found = violations(
+                &document,
+                &reference::<T>(),
+                &value,
+                &T::schema_name(),
+                true,
+            );
+            assert!(found.is_empty(), "{}", found.join("\n"));
+        }
+    }
+
+    fn time(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    fn date() -> NaiveDate {
+        NaiveDate::from_ymd(2026, 11, 5)
+    }
+
+    fn start(court_time: &str) -> DateTime<FixedOffset> {
+        club_time(date().and_time(time(court_time)))
+    }
+
+    fn booking() -> Booking {
+        Booking {
+            id: "42".to_string(),
+            start: start("19:40"),
+            court_number: 2,
+        }
+    }
+
+    fn outcomes() -> Vec<BookingOutcome> {
+        vec![
+            BookingOutcome::Booked {
+                start: booking().start,
+                court_number: 2,
+            },
+            BookingOutcome::FellBack {
+                wanted_start: booking().start,
+                start: start("20:20"),
+                court_number: 1,
+            },
+            BookingOutcome::NothingFree {
+                starts: vec![booking().start, start("20:20")],
+            },
+            BookingOutcome::LoginFailed {
+                date: date(),
+                reason: "wrong password".to_string(),
+            },
+            BookingOutcome::NeverOpened { date: date() },
+            BookingOutcome::Unconfirmed {
+                date: date(),
+                reason: "timeout".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn bookings_match_their_schemas() {
+        assert_valid(&[ErrorContainer::new(vec!["invalid".to_string()])]);
+        assert_valid(&[NewBooking {
+            slot_id: "42".to_string(),
+            start: booking().start,
+        }]);
+        assert_valid(&[booking()]);
+        assert_valid(&[CourtWithId {
+            court_number: 3,
+            booking_id: "42".to_string(),
+        }]);
+        assert_valid(&outcomes());
+        let page = Page::of(vec![booking()], None, None).unwrap();
+        let found = violations(
+            &openapi(),
+            &reference::<Page<Booking>>(),
+            &serde_json::to_value(&page).unwrap(),
+            "Page",
+            true,
+        );
+        assert!(found.is_empty(), "{}", found.join("\n"));
+    }
+
+    #[test]
+    fn watchers_match_their_schemas() {
+        let sniper = Sniper {
+            until: time("21:00"),
+            interval_seconds: 60,
+            budget_minutes: 360,
+        };
+        let weekly: Watcher = serde_json::from_value(json!({
+            "name": "thursday",
+            "court_time": "19:40",
+            "week_day": "Thursday",
+        }))
+        .unwrap();
+        let mut once = weekly.clone();
+        once.name = "once".to_string();
+        once.schedule = Schedule::Once { date: date() };
+        once.kind = WatcherKind::Sniper(sniper);
+        once.skip = vec!["2026-12-21..2027-01-03".parse::<SkipPeriod>().unwrap()];
+        once.created_at = Some(Utc::now());
+        once.updated_at = Some(Utc::now());
+        once.next_attempt = Some(booking().start);
+        let watchers = outcomes()
+            .into_iter()
+            .map(|outcome| {
+                let mut watcher = once.clone();
+                watcher.last_outcome = Some(outcome);
+                watcher
+            })
+            .chain(vec![weekly])
+            .collect::<Vec<_>>();
+        assert_valid(&watchers);
+        assert_valid(&[
+            WatcherStatus::Draft,
+            WatcherStatus::Deployed,
+            WatcherStatus::Running,
+            WatcherStatus::WaitingForOpening,
+            WatcherStatus::Booked,
+            WatcherStatus::Failed,
+            WatcherStatus::Paused,
+            WatcherStatus::Done,
+        ]);
+        assert_valid(&[WatcherKind::Opening, WatcherKind::Sniper(sniper)]);
+        assert_valid(&[sniper]);
+    }
+
+    #[test]
+    fn planning_and_clock_match_their_schemas() {
+        let cells = vec![
+            PlanningCell {
+                time: time("19:40"),
+                free: true,
+                slot_id: Some("42".to_string()),
+            },
+            PlanningCell {
+                time: time("19:40"),
+                free: false,
+                slot_id: None,
+            },
+        ];
+        assert_valid(&[Planning {
+            from: date(),
+            to: date(),
+            days: vec![planning_day(date(), &cells, &[booking()])],
+        }]);
+        let sample = ClockSample {
+            sent: Utc::now(),
+            round_trip_ms: 80,
+            server_date: Utc::now(),
+        };
+        assert_valid(&[ClockEstimate::from_samples(vec![sample]).unwrap()]);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::vault::to_hex;
+use crate::Result;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use failure::bail;
+use std::env;
+use std::str::FromStr;
+
+/// What an API key gives access to, `Admin` includes `Read`.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Scope {
+    Read,
+    Admin,
+}
+
+impl FromStr for Scope {
+    type Err = crate::Error;
+
+    fn from_str(scope: &str) -> Result<Self> {
+        match scope {
+            "read" => Ok(Scope::Read),
+            "admin" => Ok(Scope::Admin),
+            _ => bail!("{} is not a valid scope, should be read or admin", scope),
+        }
+    }
+}
+
+impl Scope {
+    pub fn allows(self, required: Scope) -> bool {
+        self >= required
+    }
+}
+
+/// Keys accepted by the proxy, either as `X-Api-Key` or as a bearer token.
+#[derive(Debug, Clone)]
+pub struct ApiKeys {
+    keys: Vec<(String, Scope)>,
+}
+
+impl FromStr for ApiKeys {
+    type Err = crate::Error;
+
+    /// Parses `key:scope` pairs separated by commas, e.g. `k3y:admin,r3ad:read`.
+    fn from_str(keys: &str) -> Result<Self> {
+        let mut parsed = vec![];
+        for key in keys.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+            match key.rfind(':') {
+                Some(index) if index > 0 => {
+                    parsed.push((key[..index].to_string(), key[index + 1..].parse()?))
+                }
+                _ => bail!("api key {} should be given as key:scope", key),
+            }
+        }
+        if parsed.is_empty() {
+            bail!("at least one api key should be given");
+        }
+        Ok(ApiKeys { keys: parsed })
+    }
+}
+
+impl ApiKeys {
+    pub fn from_env() -> Result<Self> {
+        match env::var("api_keys") {
+            Ok(keys) => keys.parse(),
+            Err(_) => bail!("environment variable api_keys should be set"),
+        }
+    }
+
+    /// Scope of the given key, compared in constant time to every key not to leak valid
+    /// keys.
+    pub fn scope(&self, key: &str) -> Option<Scope> {
+        let mut found = None;
+        for (known, scope) in &self.keys {
+            if fixed_time_eq(known.as_bytes(), key.as_bytes()) {
+                found = Some(*scope);
+            }
+        }
+        found
+    }
+}
+
+/// Compares all the bytes whatever the first difference, only the length of the
+/// compared values leaks.
+fn fixed_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    let mut difference = 0;
+    for (left, right) in left.iter().zip(right) {
+        difference |= left ^ right;
+    }
+    difference == 0
+}
+
+/// Signs the links of notifications, e.g. to cancel a booking from an email, so that
+/// they work without an API key until they expire.
+pub struct ActionSigner {
+    key: Vec<u8>,
+}
+
+impl ActionSigner {
+    pub fn new(key: &str) -> Self {
+        ActionSigner {
+            key: key.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn from_env() -> Result<Self> {
+        match env::var("action_signing_key") {
+            Ok(ref key) if !key.is_empty() => Ok(ActionSigner::new(key)),
+            _ => bail!("environment variable action_signing_key should be set"),
+        }
+    }
+
+    /// HMAC-SHA256 of the action on `id` until the `expires` timestamp, in hex.
+    pub fn sign(&self, action: &str, id: &str, expires: i64) -> String {
+        let mut hmac = Hmac::new(Sha256::new(), &self.key);
+        hmac.input(format!("{}:{}:{}", action, id, expires).as_bytes());
+        to_hex(hmac.result().code())
+    }
+
+    /// Whether the signature is the one of the action and hasn't expired at `now`.
+    pub fn verify(&self, action: &str, id: &str, expires: i64, signature: &str, now: i64) -> bool {
+        let expected = self.sign(action, id, expires);
+        now <= expires && fixed_time_eq(expected.as_bytes(), signature.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_keys_are_key_scope_pairs() {
+        let keys = "k3y:admin, r3ad:read,co:lon:read,"
+            .parse::<ApiKeys>()
+            .unwrap();
+        assert_eq!(keys.scope("k3y"), Some(Scope::Admin));
+        assert_eq!(keys.scope("r3ad"), Some(Scope::Read));
+        assert_eq!(keys.scope("co:lon"), Some(Scope::Read));
+        assert_eq!(keys.scope("k3"), None);
+        assert_eq!(keys.scope("k3y:admin"), None);
+        for invalid in &["k3y:write", "k3y", ":admin", "", " , "] {
+            assert!(invalid.parse::<ApiKeys>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn admin_includes_read() {
+        assert!(Scope::Admin.allows(Scope::Read));
+        assert!(Scope::Admin.allows(Scope::Admin));
+        assert!(Scope::Read.allows(Scope::Read));
+        assert!(!Scope::Read.allows(Scope::Admin));
+    }
+
+    #[test]
+    fn signatures_are_for_one_action_until_they_expire() {
+        let signer = ActionSigner::new("s3cret");
+        let signature = signer.sign("cancel", "42", 1_000);
+        assert_eq!(signature.len(), 64);
+        assert!(signer.verify("cancel", "42", 1_000, &signature, 1_000));
+        assert!(!signer.verify("cancel", "42", 1_000, &signature, 1_001));
+        // a later expiry or another booking needs another signature
+        assert!(!signer.verify("cancel", "42", 2_000, &signature, 1_000));
+        assert!(!signer.verify("cancel", "43", 1_000, &signature, 1_000));
+        assert!(!signer.verify("book", "42", 1_000, &signature, 1_000));
+        let mut tampered = signature.clone().into_bytes();
+        tampered[0] = if tampered[0] == b'0' { b'1' } else { b'0' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert!(!signer.verify("cancel", "42", 1_000, &tampered, 1_000));
+        assert!(!signer.verify("cancel", "42", 1_000, &signature[1..], 1_000));
+        let other = ActionSigner::new("other");
+        assert!(!other.verify("cancel", "42", 1_000, &signature, 1_000));
+    }
+}
@@ -0,0 +1,784 @@
+use crate::booker::{Schedule, WatcherKind};
+use crate::notifier::BookingOutcome;
+use crate::runtime::RunState;
+use crate::runtime::WatcherRuntime;
+use crate::store::OutcomeStore;
+use crate::watcher::{Compose, CredentialSecrets, Service, Watcher, WatcherStatus, WANAPLAY_STACK};
+use crate::Result;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output};
+use std::sync::Mutex;
+
+const DEFAULT_BOOKER_PATH: &str = "/usr/local/bin/wanaplay-booker";
+const SYSTEMD_UNIT_PREFIX: &str = "wanaplay-";
+/// Secrets a unit reads from the credentials file, when not given as a `_FILE`.
+const CREDENTIAL_VARS: [&str; 3] = ["wanaplay_login", "wanaplay_password", "vault_passphrase"];
+/// Paths and settings a unit gets as they are, the vault and its key file stay where
+/// the proxy reads them.
+const FORWARDED_VARS: [&str; 5] = [
+    "vault_path",
+    "vault_key_file",
+    "wanaplay_account",
+    "booking_partners",
+    "outcome_dir",
+];
+
+/// Where and how watchers run.
+pub trait WatcherBackend: Send + Sync {
+    /// Runs exactly the given watchers, stopping the ones no longer listed.
+    fn deploy(&self, watchers: &[Watcher]) -> Result<()>;
+
+    /// Starts the watcher with its current arguments, restarting it when it runs with
+    /// older ones.
+    fn start(&self, watcher: &Watcher) -> Result<()>;
+
+    fn stop(&self, watcher: &Watcher) -> Result<()>;
+
+    /// Status of the deployed watcher, `None` when the backend doesn't run it.
+    fn status(&self, watcher: &Watcher) -> Option<WatcherStatus>;
+
+    /// Outcome of the last booking attempt, as the booker recorded it.
+    fn last_outcome(&self, watcher: &Watcher) -> Option<BookingOutcome> {
+        OutcomeStore::from_env().load(&watcher.name)
+    }
+
+    /// Whether watchers stop with the current process and have to be deployed again
+    /// on launch.
+    fn is_embedded(&self) -> bool {
+        false
+    }
+}
+
+/// Selects the backend from `watcher_backend`: `swarm` (the default), `compose`,
+/// `systemd`, `process`, `in_process` or `fake`.
+pub fn backend_from_env() -> Result<Box<dyn WatcherBackend>> {
+    match env::var("watcher_backend").as_ref().map(String::as_str) {
+        Err(_) | Ok("swarm") => Ok(Box::new(SwarmBackend)),
+        Ok("compose") => Ok(Box::new(ComposeBackend)),
+        Ok("systemd") => Ok(Box::new(SystemdBackend)),
+        Ok("process") => Ok(Box::new(ProcessBackend::default())),
+        Ok("in_process") => Ok(Box::new(InProcessBackend::new(WatcherRuntime::from_env()?))),
+        Ok("fake") => Ok(Box::new(FakeBackend::default())),
+        Ok(backend) => bail!("{} is not a valid watcher backend", backend),
+    }
+}
+
+/// Arguments of the `wanaplay-booker` command running the watcher.
+pub fn booker_args(watcher: &Watcher) -> Vec<String> {
+    let mut args = vec![
+        "--name".to_string(),
+        watcher.name.clone(),
+        "-c".to_string(),
+        format!("{}:00", watcher.court_time.format("%H:%M")),
+    ];
+    for fallback in &watcher.fallback_times {
+        args.push("-f".to_string());
+        args.push(format!("{}:00", fallback.format("%H:%M")));
+    }
+    match watcher.schedule {
+        Schedule::Weekly { week_day } => {
+            args.push("-w".to_string());
+            args.push(crate::serde_week_day::name(&week_day).to_string());
+        }
+        Schedule::Once { date } => {
+            args.push("-d".to_string());
+            args.push(date.to_string());
+        }
+    }
+    if let WatcherKind::Sniper(sniper) = watcher.kind {
+        args.push("--snipe_until".to_string());
+        args.push(format!("{}:00", sniper.until.format("%H:%M")));
+        args.push("--interval".to_string());
+        args.push(sniper.interval_seconds.to_string());
+        args.push("--budget".to_string());
+        args.push(format!("{}m", sniper.budget_minutes));
+    }
+    for period in &watcher.skip {
+        args.push("-s".to_string());
+        args.push(period.to_string());
+    }
+    args
+}
+
+fn booker_path() -> String {
+    env::var("booker_path").unwrap_or_else(|_| DEFAULT_BOOKER_PATH.to_string())
+}
+
+fn run(command: &mut Command) -> Result<Output> {
+    let output = command.output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(output)
+}
+
+fn docker() -> Command {
+    let mut command = Command::new("docker");
+    command.arg("-H").arg("unix:///var/run/docker.sock");
+    command
+}
+
+/// Status of the latest container with all the given labels, names are matched as
+/// substrings and change between docker-compose versions.
+fn docker_status(labels: &[String]) -> Option<WatcherStatus> {
+    let mut command = docker();
+    command.arg("ps").arg("--all");
+    for label in labels {
+        command.arg("--filter").arg(format!("label={}", label));
+    }
+    let output = command.arg("--format").arg("{{.Status}}").output().ok()?;
+    // newest first, e.g. `Up 3 hours`, `Up 2 days (Paused)` or `Exited (1) 2 minutes ago`
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let status = stdout.lines().next()?.trim();
+    if status.is_empty() {
+        None
+    } else if status.contains("(Paused)") {
+        Some(WatcherStatus::Paused)
+    } else if status.starts_with("Up") {
+        Some(WatcherStatus::Running)
+    } else if status.starts_with("Created") {
+        Some(WatcherStatus::Deployed)
+    } else if status.starts_with("Exited (0)") {
+        Some(WatcherStatus::Done)
+    } else {
+        Some(WatcherStatus::Failed)
+    }
+}
+
+/// One docker swarm service per watcher, deployed with `docker stack deploy`. The
+/// credentials are docker secrets created beforehand, see `watcher::credential_secrets`.
+pub struct SwarmBackend;
+
+impl WatcherBackend for SwarmBackend {
+    fn deploy(&self, watchers: &[Watcher]) -> Result<()> {
+        let path = Compose::export(watchers, &CredentialSecrets::External)?;
+        run(docker()
+            .arg("stack")
+            .arg("deploy")
+            .arg("--prune")
+            .arg("-c")
+            .arg(path)
+            .arg(WANAPLAY_STACK))?;
+        Ok(())
+    }
+
+    fn start(&self, watcher: &Watcher) -> Result<()> {
+        let command = Service::from(watcher).command.unwrap_or_default();
+        run(docker()
+            .arg("service")
+            .arg("update")
+            .arg("--replicas")
+            .arg("1")
+            .arg("--args")
+            .arg(command)
+            .arg(format!("{}_{}", WANAPLAY_STACK, watcher.name)))?;
+        Ok(())
+    }
+
+    /// Scales the service down to 0 so that `start` can scale it back up, the next
+    /// deployment removes it if the watcher isn't enabled anymore.
+    fn stop(&self, watcher: &Watcher) -> Result<()> {
+        run(docker()
+            .arg("service")
+            .arg("scale")
+            .arg(format!("{}_{}=0", WANAPLAY_STACK, watcher.name)))?;
+        Ok(())
+    }
+
+    fn status(&self, watcher: &Watcher) -> Option<WatcherStatus> {
+        docker_status(&[format!(
+            "com.docker.swarm.service.name={}_{}",
+            WANAPLAY_STACK, watcher.name
+        )])
+    }
+}
+
+/// One container per watcher, managed with plain `docker-compose`. The credentials are
+/// read from the files of `compose_secrets_dir`, see [`CredentialSecrets::Files`].
+pub struct ComposeBackend;
+
+impl ComposeBackend {
+    fn compose(&self) -> Result<Command> {
+        let mut command = Command::new("docker-compose");
+        command
+            .arg("-f")
+            .arg(Compose::path_from_env()?)
+            .arg("-p")
+            .arg(WANAPLAY_STACK);
+        Ok(command)
+    }
+}
+
+impl WatcherBackend for ComposeBackend {
+    fn deploy(&self, watchers: &[Watcher]) -> Result<()> {
+        Compose::export(watchers, &CredentialSecrets::files_from_env())?;
+        run(self.compose()?.arg("up").arg("-d").arg("--remove-orphans"))?;
+        Ok(())
+    }
+
+    /// Recreates the container when the service of the watcher changed.
+    fn start(&self, watcher: &Watcher) -> Result<()> {
+        Compose::update(watcher, &CredentialSecrets::files_from_env())?;
+        run(self.compose()?.arg("up").arg("-d").arg(&watcher.name))?;
+        Ok(())
+    }
+
+    fn stop(&self, watcher: &Watcher) -> Result<()> {
+        run(self
+            .compose()?
+            .arg("rm")
+            .arg("--stop")
+            .arg("--force")
+            .arg(&watcher.name))?;
+        Ok(())
+    }
+
+    fn status(&self, watcher: &Watcher) -> Option<WatcherStatus> {
+        docker_status(&[
+            format!("com.docker.compose.project={}", WANAPLAY_STACK),
+            format!("com.docker.compose.service={}", watcher.name),
+        ])
+    }
+}
+
+/// One systemd user unit per watcher, in `~/.config/systemd/user`. Credentials given in
+/// plain environment variables are written to `~/.config/wanaplay/credentials.env`,
+/// readable by the user only, units are world-readable.
+pub struct SystemdBackend;
+
+impl SystemdBackend {
+    fn unit_name(watcher: &Watcher) -> String {
+        format!("{}{}.service", SYSTEMD_UNIT_PREFIX, watcher.name)
+    }
+
+    fn config_dir() -> Result<PathBuf> {
+        match env::var("HOME") {
+            Ok(home) => Ok(PathBuf::from(home).join(".config")),
+            Err(_) => bail!("environment variable HOME should be set"),
+        }
+    }
+
+    fn unit_dir() -> Result<PathBuf> {
+        Ok(SystemdBackend::config_dir()?.join("systemd/user"))
+    }
+
+    /// Writes the credentials not read from a `_FILE` to a file of mode 0600, `None`
+    /// when there are none.
+    fn write_credentials() -> Result<Option<PathBuf>> {
+        let lines = CREDENTIAL_VARS
+            .iter()
+            .filter(|var| env::var(format!("{}_FILE", var)).is_err())
+            .filter_map(|var| {
+                let value = env::var(var).ok()?;
+                Some(format!(
+                    "{}=\"{}\"",
+                    var,
+                    value.replace('\\', "\\\\").replace('"', "\\\"")
+                ))
+            })
+            .collect::<Vec<_>>();
+        if lines.is_empty() {
+            return Ok(None);
+        }
+        let dir = SystemdBackend::config_dir()?.join("wanaplay");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("credentials.env");
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?;
+        // the mode is only applied to new files
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        file.write_all((lines.join("\n") + "\n").as_bytes())?;
+        Ok(Some(path))
+    }
+
+    fn unit(watcher: &Watcher, credentials: Option<&Path>) -> String {
+        let mut lines = vec![
+            "[Unit]".to_string(),
+            format!("Description=wanaplay watcher {}", watcher.name),
+            String::new(),
+            "[Service]".to_string(),
+            format!(
+                "ExecStart={} {}",
+                booker_path(),
+                booker_args(watcher).join(" ")
+            ),
+            "Restart=on-failure".to_string(),
+        ];
+        for var in &CREDENTIAL_VARS {
+            let file_var = format!("{}_FILE", var);
+            if let Ok(path) = env::var(&file_var) {
+                lines.push(format!("Environment=\"{}={}\"", file_var, path));
+            }
+        }
+        for var in &FORWARDED_VARS {
+            if let Ok(value) = env::var(var) {
+                lines.push(format!("Environment=\"{}={}\"", var, value));
+            }
+        }
+        if let Some(credentials) = credentials {
+            lines.push(format!("EnvironmentFile={}", credentials.display()));
+        }
+        lines.push(String::new());
+        lines.push("[Install]".to_string());
+        lines.push("WantedBy=default.target".to_string());
+        lines.join("\n") + "\n"
+    }
+
+    fn write_unit(dir: &Path, watcher: &Watcher, credentials: Option<&Path>) -> Result<()> {
+        fs::write(
+            dir.join(SystemdBackend::unit_name(watcher)),
+            SystemdBackend::unit(watcher, credentials),
+        )?;
+        Ok(())
+    }
+
+    fn systemctl() -> Command {
+        let mut command = Command::new("systemctl");
+        command.arg("--user");
+        command
+    }
+}
+
+impl WatcherBackend for SystemdBackend {
+    fn deploy(&self, watchers: &[Watcher]) -> Result<()> {
+        let dir = SystemdBackend::unit_dir()?;
+        fs::create_dir_all(&dir)?;
+        let units = watchers
+            .iter()
+            .map(SystemdBackend::unit_name)
+            .collect::<Vec<_>>();
+        for entry in fs::read_dir(&dir)? {
+            let file_name = entry?.file_name().to_string_lossy().to_string();
+            if file_name.starts_with(SYSTEMD_UNIT_PREFIX) && !units.contains(&file_name) {
+                let _ = run(SystemdBackend::systemctl()
+                    .arg("disable")
+                    .arg("--now")
+                    .arg(&file_name));
+                fs::remove_file(dir.join(&file_name))?;
+            }
+        }
+        let credentials = SystemdBackend::write_credentials()?;
+        for watcher in watchers {
+            SystemdBackend::write_unit(&dir, watcher, credentials.as_deref())?;
+        }
+        run(SystemdBackend::systemctl().arg("daemon-reload"))?;
+        for unit in units {
+            run(SystemdBackend::systemctl()
+                .arg("enable")
+                .arg("--now")
+                .arg(unit))?;
+        }
+        Ok(())
+    }
+
+    fn start(&self, watcher: &Watcher) -> Result<()> {
+        let dir = SystemdBackend::unit_dir()?;
+        fs::create_dir_all(&dir)?;
+        let credentials = SystemdBackend::write_credentials()?;
+        SystemdBackend::write_unit(&dir, watcher, credentials.as_deref())?;
+        run(SystemdBackend::systemctl().arg("daemon-reload"))?;
+        run(SystemdBackend::systemctl()
+            .arg("restart")
+            .arg(SystemdBackend::unit_name(watcher)))?;
+        Ok(())
+    }
+
+    fn stop(&self, watcher: &Watcher) -> Result<()> {
+        run(SystemdBackend::systemctl()
+            .arg("stop")
+            .arg(SystemdBackend::unit_name(watcher)))?;
+        Ok(())
+    }
+
+    fn status(&self, watcher: &Watcher) -> Option<WatcherStatus> {
+        let output = SystemdBackend::systemctl()
+            .arg("is-active")
+            .arg(SystemdBackend::unit_name(watcher))
+            .output()
+            .ok()?;
+        match String::from_utf8_lossy(&output.stdout).trim() {
+            "active" | "activating" | "reloading" => Some(WatcherStatus::Running),
+            "failed" => Some(WatcherStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// Kills the booker unless it already exited.
+fn kill(child: &mut Child) -> Result<()> {
+    if child.try_wait()?.is_none() {
+        child.kill()?;
+        child.wait()?;
+    }
+    Ok(())
+}
+
+/// Watchers run as child processes of the current process, handy on a laptop.
+#[derive(Default)]
+pub struct ProcessBackend {
+    children: Mutex<HashMap<String, Child>>,
+}
+
+impl WatcherBackend for ProcessBackend {
+    fn is_embedded(&self) -> bool {
+        true
+    }
+
+    fn deploy(&self, watchers: &[Watcher]) -> Result<()> {
+        let names = watchers
+            .iter()
+            .map(|watcher| watcher.name.clone())
+            .collect::<Vec<_>>();
+        let mut children = self.children.lock().unwrap();
+        let stale = children
+            .keys()
+            .filter(|name| !names.contains(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in stale {
+            if let Some(mut child) = children.remove(&name) {
+                kill(&mut child)?;
+            }
+        }
+        drop(children);
+        for watcher in watchers {
+            // a booker done on its own isn't started again
+            match self.status(watcher) {
+                None | Some(WatcherStatus::Failed) => self.start(watcher)?,
+                Some(_) => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn start(&self, watcher: &Watcher) -> Result<()> {
+        self.stop(watcher)?;
+        let child = Command::new(booker_path())
+            .args(booker_args(watcher))
+            .spawn()?;
+        self.children
+            .lock()
+            .unwrap()
+            .insert(watcher.name.clone(), child);
+        Ok(())
+    }
+
+    fn stop(&self, watcher: &Watcher) -> Result<()> {
+        if let Some(mut child) = self.children.lock().unwrap().remove(&watcher.name) {
+            kill(&mut child)?;
+        }
+        Ok(())
+    }
+
+    fn status(&self, watcher: &Watcher) -> Option<WatcherStatus> {
+        let mut children = self.children.lock().unwrap();
+        match children.get_mut(&watcher.name)?.try_wait() {
+            Ok(None) => Some(WatcherStatus::Running),
+            Ok(Some(exit)) if exit.success() => Some(WatcherStatus::Done),
+            Ok(Some(_)) | Err(_) => Some(WatcherStatus::Failed),
+        }
+    }
+}
+
+/// Watchers run as tasks of the current process, see [`WatcherRuntime`].
+pub struct InProcessBackend {
+    runtime: WatcherRuntime,
+}
+
+impl InProcessBackend {
+    pub fn new(runtime: WatcherRuntime) -> Self {
+        InProcessBackend { runtime }
+    }
+}
+
+impl WatcherBackend for InProcessBackend {
+    fn is_embedded(&self) -> bool {
+        true
+    }
+
+    fn deploy(&self, watchers: &[Watcher]) -> Result<()> {
+        for status in self.runtime.statuses() {
+            if !watchers.iter().any(|watcher| watcher.name == status.name) {
+                self.runtime.stop(&status.name);
+            }
+        }
+        for watcher in watchers {
+            self.runtime.start(watcher);
+        }
+        Ok(())
+    }
+
+    fn start(&self, watcher: &Watcher) -> Result<()> {
+        self.runtime.start(watcher);
+        Ok(())
+    }
+
+    fn stop(&self, watcher: &Watcher) -> Result<()> {
+        self.runtime.stop(&watcher.name);
+        Ok(())
+    }
+
+    fn status(&self, watcher: &Watcher) -> Option<WatcherStatus> {
+        let status = self.runtime.status(&watcher.name)?;
+        Some(match (status.state, &status.last_outcome) {
+            (RunState::Stopped, _) => return None,
+            (RunState::Attempting, _) => WatcherStatus::WaitingForOpening,
+            (RunState::Running, Some(outcome)) => WatcherStatus::after(outcome),
+            (RunState::Running, None) => WatcherStatus::Running,
+        })
+    }
+
+    fn last_outcome(&self, watcher: &Watcher) -> Option<BookingOutcome> {
+        self.runtime
+            .status(&watcher.name)
+            .and_then(|status| status.last_outcome)
+            .or_else(|| OutcomeStore::from_env().load(&watcher.name))
+    }
+}
+
+/// Only records what it is asked to do, for tests and demos.
+#[derive(Default)]
+pub struct FakeBackend {
+    statuses: Mutex<HashMap<String, WatcherStatus>>,
+}
+
+impl WatcherBackend for FakeBackend {
+    fn deploy(&self, watchers: &[Watcher]) -> Result<()> {
+        let mut statuses = self.statuses.lock().unwrap();
+        statuses.clear();
+        for watcher in watchers {
+            statuses.insert(watcher.name.clone(), WatcherStatus::Running);
+        }
+        Ok(())
+    }
+
+    fn start(&self, watcher: &Watcher) -> Result<()> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .insert(watcher.name.clone(), WatcherStatus::Running);
+        Ok(())
+    }
+
+    fn stop(&self, watcher: &Watcher) -> Result<()> {
+        self.statuses.lock().unwrap().remove(&watcher.name);
+        Ok(())
+    }
+
+    fn status(&self, watcher: &Watcher) -> Option<WatcherStatus> {
+        self.statuses.lock().unwrap().get(&watcher.name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::with_store;
+    use crate::store::WatcherStore;
+    use crate::watcher::{
+        add_watcher, deploy_watchers, get_watcher, pause_watcher, remove_watcher, resume_watcher,
+        update_watcher,
+    };
+    use chrono::{NaiveDate, NaiveTime};
+
+    fn watcher(name: &str) -> Watcher {
+        serde_json::from_value(json!({
+            "name": name,
+            "court_time": "19:40",
+            "week_day": "Thursday",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn fake_backend_records_deploy_start_and_stop() {
+        let backend = FakeBackend::default();
+        let (first, second) = (watcher("first"), watcher("second"));
+        backend.deploy(&[first.clone(), second.clone()]).unwrap();
+        assert_eq!(backend.status(&first), Some(WatcherStatus::Running));
+        assert_eq!(backend.status(&second), Some(WatcherStatus::Running));
+        backend.stop(&first).unwrap();
+        assert_eq!(backend.status(&first), None);
+        backend.start(&first).unwrap();
+        assert_eq!(backend.status(&first), Some(WatcherStatus::Running));
+        backend.deploy(std::slice::from_ref(&second)).unwrap();
+        assert_eq!(backend.status(&first), None);
+        assert_eq!(backend.status(&second), Some(WatcherStatus::Running));
+    }
+
+    #[test]
+    fn pausing_a_watcher_leaves_the_others_alone() {
+        with_store(|| {
+            let backend = FakeBackend::default();
+            add_watcher(watcher("thursday")).unwrap();
+            add_watcher(watcher("friday")).unwrap();
+            deploy_watchers(&backend).unwrap();
+            let friday = get_watcher(&backend, "friday").unwrap().unwrap();
+            // a redeployment would start it again
+            backend.stop(&friday).unwrap();
+            let paused = pause_watcher(&backend, "thursday").unwrap();
+            assert!(!paused.enabled);
+            assert_eq!(backend.status(&paused), None);
+            assert_eq!(backend.status(&friday), None);
+            let paused = get_watcher(&backend, "thursday").unwrap().unwrap();
+            assert_eq!(paused.status, WatcherStatus::Paused);
+            assert!(paused.next_attempt.is_none());
+            let resumed = resume_watcher(&backend, "thursday").unwrap();
+            assert_eq!(backend.status(&resumed), Some(WatcherStatus::Running));
+            let resumed = get_watcher(&backend, "thursday").unwrap().unwrap();
+            assert!(resumed.next_attempt.is_some());
+            assert_eq!(backend.status(&friday), None);
+        });
+    }
+
+    #[test]
+    fn updating_a_deployed_watcher_keeps_its_status_and_outcome() {
+        with_store(|| {
+            let backend = FakeBackend::default();
+            add_watcher(watcher("thursday")).unwrap();
+            deploy_watchers(&backend).unwrap();
+            let outcome = BookingOutcome::NeverOpened {
+                date: NaiveDate::from_ymd(2026, 11, 5),
+            };
+            OutcomeStore::from_env().save("thursday", &outcome).unwrap();
+            // what a PUT sends, without the fields the server tracks
+            let mut changed = watcher("thursday");
+            changed.court_time = NaiveTime::from_hms(20, 20, 0);
+            let updated = update_watcher(&backend, changed).unwrap();
+            assert_eq!(updated.court_time, NaiveTime::from_hms(20, 20, 0));
+            assert_eq!(updated.status, WatcherStatus::Running);
+            assert!(updated.last_outcome.is_some());
+            let stored = WatcherStore::from_env().get("thursday").unwrap().unwrap();
+            assert_eq!(stored.status, WatcherStatus::Deployed);
+            // still applied once paused
+            pause_watcher(&backend, "thursday").unwrap();
+            assert_eq!(backend.status(&stored), None);
+        });
+    }
+
+    #[test]
+    fn deployed_watchers_read_the_vault_of_the_proxy() {
+        with_store(|| {
+            let compose =
+                env::temp_dir().join(format!("wanaplay-compose-{}.yml", std::process::id()));
+            env::set_var("compose_file_path", &compose);
+            env::set_var("vault_path", "/etc/wanaplay/vault.json");
+            env::set_var("vault_key_file", "/etc/wanaplay/vault.key");
+            let thursday = watcher("thursday");
+            Compose::export(
+                std::slice::from_ref(&thursday),
+                &CredentialSecrets::External,
+            )
+            .unwrap();
+            let swarm = fs::read_to_string(&compose).unwrap();
+            Compose::export(
+                std::slice::from_ref(&thursday),
+                &CredentialSecrets::Files(PathBuf::from("./secrets")),
+            )
+            .unwrap();
+            let files = fs::read_to_string(&compose).unwrap();
+            let unit = SystemdBackend::unit(&thursday, None);
+            env::remove_var("vault_path");
+            env::remove_var("vault_key_file");
+            let _ = fs::remove_file(&compose);
+            for exported in &[&swarm, &files] {
+                assert!(exported.contains("vault_path=/run/secrets/wanaplay_vault"));
+                assert!(exported.contains("vault_key_file=/run/secrets/wanaplay_vault_key"));
+                assert!(!exported.contains("wanaplay_password"));
+            }
+            assert!(swarm.contains("external: true"));
+            assert!(files.contains("file: /etc/wanaplay/vault.json"));
+            assert!(files.contains("file: /etc/wanaplay/vault.key"));
+            assert!(unit.contains("Environment=\"vault_path=/etc/wanaplay/vault.json\""));
+            assert!(unit.contains("Environment=\"vault_key_file=/etc/wanaplay/vault.key\""));
+        });
+    }
+
+    #[test]
+    fn exited_bookers_are_done_or_failed_after_their_exit_code() {
+        with_store(|| {
+            let backend = ProcessBackend::default();
+            let (done, failed) = (watcher("done"), watcher("failed"));
+            env::set_var("booker_path", "true");
+            backend.start(&done).unwrap();
+            env::set_var("booker_path", "false");
+            backend.start(&failed).unwrap();
+            env::remove_var("booker_path");
+            for _ in 0..100 {
+                if backend.status(&done) != Some(WatcherStatus::Running)
+                    && backend.status(&failed) != Some(WatcherStatus::Running)
+                {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+            assert_eq!(backend.status(&done), Some(WatcherStatus::Done));
+            assert_eq!(backend.status(&failed), Some(WatcherStatus::Failed));
+            backend.stop(&done).unwrap();
+            assert_eq!(backend.status(&done), None);
+        });
+    }
+
+    #[test]
+    fn watchers_are_deployed_and_removed_through_the_backend() {
+        with_store(|| {
+            let backend = FakeBackend::default();
+            add_watcher(watcher("thursday")).unwrap();
+            deploy_watchers(&backend).unwrap();
+            let deployed = get_watcher(&backend, "thursday").unwrap().unwrap();
+            assert_eq!(deployed.status, WatcherStatus::Running);
+            assert!(deployed.last_outcome.is_none());
+            let outcome = BookingOutcome::NeverOpened {
+                date: NaiveDate::from_ymd(2026, 11, 5),
+            };
+            OutcomeStore::from_env().save("thursday", &outcome).unwrap();
+            let deployed = get_watcher(&backend, "thursday").unwrap().unwrap();
+            assert!(deployed.last_outcome.is_some());
+            remove_watcher(&backend, &deployed).unwrap();
+            assert!(OutcomeStore::from_env().load("thursday").is_none());
+            assert_eq!(backend.status(&deployed), None);
+            assert!(get_watcher(&backend, "thursday").unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn fallback_times_are_validated_and_passed_to_the_booker() {
+        let mut fallback: Watcher = serde_json::from_value(json!({
+            "name": "fallback",
+            "court_time": "19:40",
+            "fallback_times": ["20:20", "19:00"],
+            "week_day": "Thursday",
+        }))
+        .unwrap();
+        assert!(fallback.validation_errors().is_empty());
+        let args = booker_args(&fallback);
+        let flags = args.windows(2).filter(|pair| pair[0] == "-f");
+        let times = flags.map(|pair| pair[1].as_str()).collect::<Vec<_>>();
+        assert_eq!(times, ["20:20:00", "19:00:00"]);
+        assert_eq!(
+            serde_json::to_value(&fallback).unwrap()["fallback_times"],
+            json!(["20:20", "19:00"])
+        );
+
+        fallback.fallback_times.push(NaiveTime::from_hms(19, 40, 0));
+        fallback.fallback_times.push(NaiveTime::from_hms(20, 20, 0));
+        fallback.fallback_times.push(NaiveTime::from_hms(19, 45, 0));
+        assert_eq!(fallback.validation_errors().len(), 3);
+
+        fallback.fallback_times.truncate(2);
+        fallback.kind = serde_json::from_value(json!({"sniper": {"until": "21:00"}})).unwrap();
+        assert_eq!(
+            fallback.validation_errors(),
+            ["a sniper can't have fallback times"]
+        );
+    }
+}
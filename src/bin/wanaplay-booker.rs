@@ -11,112 +11,120 @@ use chrono::Weekday;
 use std::env;
 use std::thread;
 use std::time;
-extern crate select;
-#[macro_use]
 extern crate failure;
 use failure::bail;
-use select::document::Document;
-use select::predicate::Class;
 pub type Error = failure::Error;
 pub type Result<T> = std::result::Result<T, Error>;
+use wanaplay_booker::booker::{
+    next_attempt, notify, snipe, BookerParameters, Schedule, SkipPeriod, Sniper, WatcherKind,
+};
+use wanaplay_booker::notifier::{notifier_from_env, BookingOutcome, Notifiers};
+use wanaplay_booker::opening::book_at_opening;
+use wanaplay_booker::runtime::Session;
+use wanaplay_booker::schedule::parse_duration;
+use wanaplay_booker::store::OutcomeStore;
 use wanaplay_booker::*;
 
 struct Parameters {
-    weekday: Weekday,
-    court_time: NaiveTime,
+    name: Option<String>,
+    booker: BookerParameters,
     wanaplay_credentials: WanaplayCredentials,
 }
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "wanaplay-booker", about = " Book a court when available")]
 struct Opt {
-    /// week day to book
+    /// week day to book every week
     #[structopt(short = "w", long = "weekday")]
-    weekday: String,
+    weekday: Option<String>,
+    /// single date to book instead of a week day, e.g. 2026-11-05
+    #[structopt(short = "d", long = "date")]
+    date: Option<NaiveDate>,
     /// court time
     #[structopt(short = "c", long = "court_time")]
     court_time: NaiveTime,
+    /// court times to try, in order, when court_time is not free anymore
+    #[structopt(short = "f", long = "fallback_time")]
+    fallback_times: Vec<NaiveTime>,
+    /// dates not to book, e.g. 2026-11-05 or 2026-12-21..2027-01-03
+    #[structopt(short = "s", long = "skip")]
+    skip: Vec<SkipPeriod>,
+    /// snipe cancellations of courts starting from court_time until this time
+    #[structopt(long = "snipe_until")]
+    snipe_until: Option<NaiveTime>,
+    /// seconds between two polls of the planning when sniping
+    #[structopt(long = "interval", default_value = "60")]
+    interval: u64,
+    /// how long before court_time to start sniping, e.g. 6h
+    #[structopt(long = "budget", default_value = "6h")]
+    budget: String,
+    /// watcher the outcomes are recorded for, in outcome_dir
+    #[structopt(long = "name")]
+    name: Option<String>,
 }
 
 fn validate_args(opt: &mut Opt) -> Result<Parameters> {
-    let mut valid_times = vec![];
-    let mut start_time = NaiveTime::from_hms(9, 0, 0);
-    let end_time = NaiveTime::from_hms(23, 0, 0);
-    while start_time <= end_time {
-        valid_times.push(start_time.clone());
-        start_time = match start_time.overflowing_add_signed(Duration::minutes(40)) {
-            (val, _) => val,
-        };
-    }
-    if !valid_times.contains(&opt.court_time) {
-        bail!(format!(
-            "{} is not a valid court time, should be one of {:?}",
-            opt.court_time, valid_times
-        ));
+    let valid_times = court_times();
+    for court_time in std::iter::once(&opt.court_time).chain(opt.fallback_times.iter()) {
+        if !valid_times.contains(court_time) {
+            bail!(format!(
+                "{} is not a valid court time, should be one of {:?}",
+                court_time, valid_times
+            ));
+        }
     }
-    let weekday = match opt.weekday.parse::<Weekday>() {
-        Ok(v) => v,
-        Err(_) => bail!(format!("{} is not a valid week day", opt.weekday)),
+    let kind = match opt.snipe_until {
+        Some(_) if !opt.fallback_times.is_empty() => {
+            bail!("fallback times don't apply when sniping, every court time until snipe_until is tried")
+        }
+        Some(until) if until < opt.court_time || !valid_times.contains(&until) => bail!(format!(
+            "{} is not a valid sniping limit, should be a court time after {}",
+            until, opt.court_time
+        )),
+        Some(until) => WatcherKind::Sniper(Sniper {
+            until,
+            interval_seconds: opt.interval,
+            budget_minutes: parse_duration(&opt.budget)?.num_minutes(),
+        }),
+        None => WatcherKind::Opening,
     };
-    match (env::var("wanaplay_login"), env::var("wanaplay_password")) {
-        (Ok(login), Ok(password)) => Ok(Parameters {
-            weekday,
+    let schedule = match (&opt.weekday, opt.date) {
+        (Some(weekday), None) => match weekday.parse::<Weekday>() {
+            Ok(week_day) => Schedule::Weekly { week_day },
+            Err(_) => bail!(format!("{} is not a valid week day", weekday)),
+        },
+        (None, Some(date)) => Schedule::Once { date },
+        (_, _) => bail!("either a week day or a date should be given"),
+    };
+    Ok(Parameters {
+        name: opt.name.clone(),
+        booker: BookerParameters {
+            kind,
+            schedule,
             court_time: opt.court_time,
-            wanaplay_credentials: WanaplayCredentials {
-                login,
-                password: WanaplayPassword {
-                    secret_password: password,
-                },
-            },
-        }),
-        (_, _) => Err(format_err!(
-            "environment variable wanaplay_login and wanaplay_password should be set"
-        )),
-    }
+            fallback_times: opt.fallback_times.clone(),
+            skip: opt.skip.clone(),
+        },
+        wanaplay_credentials: get_credentials()?,
+    })
 }
 
-fn is_openned(client: &reqwest::Client, target_date: NaiveDate) -> bool {
-    let forbidden = "Vous ne pouvez pas voir le planning";
-    println!("watch_openning {:?} at {:?}", target_date, Local::now());
-    let mut response = client
-        .post(wanaplay_route("reservation/planning2").as_str())
-        .form(&[("date", target_date.format("%Y-%m-%d").to_string())])
-        .send()
-        .unwrap();
-    !response.text().unwrap().contains(forbidden)
+/// Notifies the outcome and records it for the proxy when the watcher is named.
+fn report(name: &Option<String>, notifier: &Notifiers, outcome: BookingOutcome) {
+    if let Some(name) = name {
+        if let Err(err) = OutcomeStore::from_env().save(name, &outcome) {
+            eprintln!("unable to record the outcome: {}", err);
+        }
+    }
+    notify(notifier, outcome);
 }
 
-fn find_book_ids(
-    client: &reqwest::Client,
-    target_date: NaiveDate,
-    court_time: NaiveTime,
-) -> Vec<String> {
-    println!("finding ids for {:?} at {:?}", target_date, court_time);
-    let response = client
-        .post(wanaplay_route("reservation/planning2").as_str())
-        .form(&[("date", target_date.format("%Y-%m-%d").to_string())])
-        .send()
-        .unwrap();
-
-    let document = Document::from_read(response).unwrap();
-    let ids = document
-        .find(Class("creneauLibre"))
-        .filter(|node| {
-            node.children()
-                .next()
-                .unwrap()
-                .children()
-                .next()
-                .unwrap()
-                .text()
-                == court_time.format("%H:%M").to_string()
-        })
-        .filter(|node| node.attr("class").unwrap() == "creneauLibre")
-        .map(|node| node.attr("onclick").unwrap())
-        .map(|link| link.split("idTspl=").collect::<Vec<_>>()[1].replace("\"", ""))
-        .collect::<Vec<_>>();
-    println!("{:?}", ids);
-    ids
+/// Whether nothing is left to do after the outcome: a one-shot watcher booked its court.
+fn is_done(parameters: &BookerParameters, outcome: &BookingOutcome) -> bool {
+    match parameters.schedule {
+        Schedule::Once { .. } => outcome.is_booked(),
+        Schedule::Weekly { .. } => false,
+    }
 }
 
 fn main() {
@@ -132,6 +140,7 @@ fn main() {
 fn run() -> Result<()> {
     let mut opt = Opt::from_args();
     let parameters = validate_args(&mut opt)?;
+    let notifier = notifier_from_env()?;
     //    let client = authenticate(
     //        parameters.wanaplay_credentials.login.clone(),
     //        parameters.wanaplay_credentials.password.crypted(),
@@ -149,46 +158,76 @@ fn run() -> Result<()> {
     //        let user_infos = get_user_infos(&client, &id)?;
     //        do_booking(&client, &user_infos, &id, &target_date);
     //    }
+    let session = Session::new(parameters.wanaplay_credentials);
+    // fail fast on wrong credentials
+    session.client()?;
+    let mut login_failure_notified = None;
+    let mut attempted = None;
     loop {
         let now: DateTime<Local> = match env::var("fake_date") {
             Ok(fake_date) => fake_date.parse::<DateTime<Local>>().unwrap(),
             Err(_) => Local::now(),
         };
         println!("loop {:?}", now);
-        let client = authenticate(
-            parameters.wanaplay_credentials.login.clone(),
-            parameters.wanaplay_credentials.password.crypted(),
-        )?;
-        if now.weekday() == parameters.weekday.pred() {
-            let target_date = now + Duration::days(15);
-            let target_date =
-                NaiveDate::from_ymd(target_date.year(), target_date.month(), target_date.day());
-            println!("target_date = {:?}", target_date);
-            if now.hour() == 23 {
-                if now.minute() >= 58 {
-                    while !is_openned(&client, target_date) {
-                        thread::sleep(time::Duration::from_secs(2));
-                    }
-                    let ids = find_book_ids(&client, target_date, parameters.court_time);
-                    if !ids.is_empty() {
-                        let id = match ids.len() {
-                            4 => ids[1].clone(),
-                            _ => ids[0].clone(),
-                        };
-                        let user_infos = get_user_infos(&client, &id)?;
-                        do_booking(&client, &user_infos, &id, &target_date);
-                    }
-                } else {
-                    println!("sleep for 1 min");
-                    thread::sleep(time::Duration::from_secs(60));
-                }
-            } else {
-                println!("sleep for 50 min");
-                thread::sleep(time::Duration::from_secs(50 * 60));
+        let (attempt_at, target_date) = match next_attempt(&parameters.booker, now) {
+            Some((_, target_date)) if attempted == Some(target_date) => {
+                println!("{:?} already attempted, sleep for 23h", target_date);
+                thread::sleep(time::Duration::from_secs(23 * 60 * 60));
+                continue;
             }
-        } else {
+            Some(next) => next,
+            None => {
+                println!("nothing left to book");
+                return Ok(());
+            }
+        };
+        println!("target_date = {:?}", target_date);
+        let wait = attempt_at - now;
+        if wait > Duration::hours(24) {
             println!("sleep for 23h");
             thread::sleep(time::Duration::from_secs(23 * 60 * 60));
+        } else if wait > Duration::hours(1) {
+            println!("sleep for 50 min");
+            thread::sleep(time::Duration::from_secs(50 * 60));
+        } else if wait > Duration::zero() {
+            println!("sleep for 1 min");
+            thread::sleep(time::Duration::from_secs(60));
+        } else {
+            if let WatcherKind::Sniper(sniper) = parameters.booker.kind {
+                let outcome = snipe(&session, &parameters.booker, &sniper, target_date, &|| {
+                    false
+                });
+                let done = is_done(&parameters.booker, &outcome);
+                report(&parameters.name, &notifier, outcome);
+                if done {
+                    return Ok(());
+                }
+                attempted = Some(target_date);
+                continue;
+            }
+            if let Err(err) = session.client() {
+                if login_failure_notified != Some(target_date) {
+                    report(
+                        &parameters.name,
+                        &notifier,
+                        BookingOutcome::LoginFailed {
+                            date: target_date,
+                            reason: err.to_string(),
+                        },
+                    );
+                    login_failure_notified = Some(target_date);
+                }
+                println!("sleep for 1 min");
+                thread::sleep(time::Duration::from_secs(60));
+                continue;
+            }
+            let outcome = book_at_opening(&session, &parameters.booker, target_date);
+            let done = is_done(&parameters.booker, &outcome);
+            report(&parameters.name, &notifier, outcome);
+            if done {
+                return Ok(());
+            }
+            attempted = Some(target_date);
         }
     }
 }
@@ -7,129 +7,152 @@ extern crate rocket_contrib;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
-extern crate regex;
 extern crate serde_json;
-extern crate serde_yaml;
 pub type Error = failure::Error;
-use failure::bail;
-use regex::Regex;
-use rocket::http::{RawStr, Status};
-use rocket::response::status;
+use rocket::http::{ContentType, RawStr, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::content::{Content, Html};
+use rocket::response::{self, status, Responder};
+use rocket::State;
 use rocket_contrib::json::Json;
-use serde_yaml::from_reader;
-use std::str;
-
-use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
-use select::document::Document;
-use select::predicate::{Attr, Class, Name};
-use std::collections::{BTreeMap, HashSet};
-use std::env;
-use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::fmt::Display;
+use std::str::{self, FromStr};
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
 use std::result::Result;
+use wanaplay_booker::api::{openapi, ErrorContainer, NewBooking, Page, API_VERSION};
+use wanaplay_booker::auth::{ActionSigner, ApiKeys, Scope};
+use wanaplay_booker::backend::{backend_from_env, WatcherBackend};
+use wanaplay_booker::booker::SkipPeriod;
+use wanaplay_booker::clock::{estimate_clock, ClockEstimate, DEFAULT_CLOCK_SAMPLES};
+use wanaplay_booker::club::{club_time, to_club_time, Club};
+use wanaplay_booker::ics::{invite, InviteConfig, InviteMethod};
+use wanaplay_booker::notifier::CANCEL_ACTION;
+use wanaplay_booker::planning::{
+    fetch_planning_range, free_slot_ids, free_times, Planning, PlanningCache, MAX_PLANNING_DAYS,
+};
+use wanaplay_booker::runtime::Session;
+use wanaplay_booker::watcher::*;
 use wanaplay_booker::*;
 
-const WANAPLAY_SERVICE_LABEL: &str = "wanaplay_type=bot";
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(deny_unknown_fields)]
-struct Service {
-    image: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    environment: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub command: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    volumes: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    ports: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    labels: Option<Vec<String>>,
-}
-
-impl From<Json<Watcher>> for Service {
-    fn from(watcher: Json<Watcher>) -> Self {
-        Service {
-            image: "touplitoui/wanaplay-booker-bot".to_string(),
-            environment: Some(vec![
-                format!("wanaplay_login={}", env::var("wanaplay_login").unwrap()),
-                format!(
-                    "wanaplay_password={}",
-                    env::var("wanaplay_password").unwrap()
-                ),
-            ]),
-            command: Some(format!(
-                "wanaplay-booker -c {}:00 -w {}",
-                watcher.court_time, watcher.week_day
-            )),
-            volumes: None,
-            ports: None,
-            labels: Some(vec![WANAPLAY_SERVICE_LABEL.to_string()]),
+/// Error of a route, answered as an `ErrorContainer` with the given status.
+#[derive(Debug)]
+struct ApiError {
+    status: Status,
+    errors: Vec<String>,
+}
+
+impl ApiError {
+    fn new<E: Display>(status: Status, error: E) -> Self {
+        ApiError {
+            status,
+            errors: vec![error.to_string()],
         }
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
-struct Compose {
-    #[serde(skip)]
-    path: PathBuf,
-    version: String,
-    services: BTreeMap<String, Service>,
-}
+    fn bad_request<E: Display>(error: E) -> Self {
+        ApiError::new(Status::BadRequest, error)
+    }
 
-impl Compose {
-    pub fn get() -> Self {
-        let path =
-            fs::canonicalize(&PathBuf::from(env::var("compose_file_path").unwrap())).unwrap();
-        let mut compose: Self = from_reader(std::fs::File::open(path.clone()).unwrap()).unwrap();
-        compose.path = path;
-        compose
+    fn not_found<E: Display>(error: E) -> Self {
+        ApiError::new(Status::NotFound, error)
     }
 
-    pub fn update(&self) {
-        let serialized_report = serde_yaml::to_string(&self).unwrap();
-        std::fs::write(self.path.clone(), serialized_report).unwrap();
+    fn internal<E: Display>(error: E) -> Self {
+        ApiError::new(Status::InternalServerError, error)
     }
 
-    pub fn add_service(&mut self, name: String, service: Service) {
-        self.services.insert(name, service);
+    /// wanaplay couldn't be reached or answered something unexpected
+    fn upstream<E: Display>(error: E) -> Self {
+        ApiError::new(Status::BadGateway, error)
     }
 
-    pub fn remove_service(&mut self, name: &str) -> Result<(), Error> {
-        if self.services.contains_key(name) {
-            self.services.remove(name);
-            Ok(())
-        } else {
-            bail!("service {:?} not found", name);
-        }
+    fn bot_not_found(id: &str) -> Self {
+        ApiError::not_found(format!("bot {} not found", id))
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-enum WatcherStatus {
-    Created,
-    Running,
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        status::Custom(self.status, Json(ErrorContainer::new(self.errors))).respond_to(request)
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct ErrorContainer {
-    pub errors: Vec<String>,
+/// Parses a required query parameter, `expected` describes its format.
+fn query<T: FromStr>(name: &str, value: Option<&RawStr>, expected: &str) -> Result<T, ApiError> {
+    let value = match value {
+        Some(value) => value
+            .url_decode()
+            .map_err(|_| ApiError::bad_request(format!("{} is not valid UTF-8", name)))?,
+        None => {
+            return Err(ApiError::bad_request(format!(
+                "query parameter {} is required",
+                name
+            )))
+        }
+    };
+    value.parse().map_err(|_| {
+        ApiError::bad_request(format!(
+            "{} is not a valid {}, expected {}",
+            value, name, expected
+        ))
+    })
 }
 
-impl ErrorContainer {
-    pub fn new(errors: Vec<String>) -> Self {
-        ErrorContainer { errors }
+fn optional_query<T: FromStr>(
+    name: &str,
+    value: Option<&RawStr>,
+    expected: &str,
+) -> Result<Option<T>, ApiError> {
+    match value {
+        Some(value) => query(name, Some(value), expected).map(Some),
+        None => Ok(None),
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct Watcher {
-    name: String,
-    status: String,
-    court_time: String,
-    week_day: String,
+/// Page of `items` asked by the `page` and `per_page` query parameters.
+fn paginate<T>(
+    items: Vec<T>,
+    page: Option<&RawStr>,
+    per_page: Option<&RawStr>,
+) -> Result<Json<Page<T>>, ApiError> {
+    Page::of(
+        items,
+        optional_query("page", page, "a positive number")?,
+        optional_query("per_page", per_page, "a positive number")?,
+    )
+    .map(Json)
+    .map_err(ApiError::bad_request)
+}
+
+fn path_date(date: &str) -> Result<NaiveDate, ApiError> {
+    date.parse().map_err(|_| {
+        ApiError::bad_request(format!("{} is not a valid date, expected YYYY-MM-DD", date))
+    })
+}
+
+/// Client of the session shared by the routes, logged in again only when it expires.
+fn logged_client(session: &Session) -> Result<reqwest::Client, ApiError> {
+    session.client().map_err(ApiError::upstream)
+}
+
+fn find_booking(client: &reqwest::Client, id: &str) -> Result<Booking, ApiError> {
+    fetch_bookings(client)
+        .map_err(ApiError::upstream)?
+        .into_iter()
+        .find(|booking| booking.id == id)
+        .ok_or_else(|| ApiError::not_found(format!("booking {} not found", id)))
+}
+
+/// Rejects watchers with invalid fields before they reach the store.
+fn validate(watcher: &Watcher) -> Result<(), ApiError> {
+    match watcher.validation_errors() {
+        ref errors if errors.is_empty() => Ok(()),
+        errors => Err(ApiError {
+            status: Status::UnprocessableEntity,
+            errors,
+        }),
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -138,339 +161,569 @@ struct TimeSlot {
     to: String,
 }
 
-impl From<Service> for Watcher {
-    fn from(service: Service) -> Self {
-        let re = Regex::new(r"wanaplay-booker -c (\d{2}:\d{2}):\d{2} -w (\w+)").unwrap();
-        let command = &service.command.unwrap();
-        let matches = re.captures(command).unwrap();
-        Watcher {
-            name: "".to_string(),
-            status: "Created".to_string(),
-            court_time: matches.get(1).unwrap().as_str().to_string(),
-            week_day: matches.get(2).unwrap().as_str().to_string(),
-        }
+type Backend = Box<dyn WatcherBackend>;
+
+/// Scope granted by the `X-Api-Key` header or the `Authorization: Bearer` token.
+fn request_scope(request: &Request) -> Result<Scope, Status> {
+    let keys = request
+        .guard::<State<ApiKeys>>()
+        .success_or(Status::InternalServerError)?;
+    let key = match request.headers().get_one("X-Api-Key") {
+        Some(key) => key,
+        None => match request.headers().get_one("Authorization") {
+            Some(authorization) if authorization.starts_with("Bearer ") => {
+                authorization["Bearer ".len()..].trim()
+            }
+            _ => return Err(Status::Unauthorized),
+        },
+    };
+    keys.scope(key).ok_or(Status::Unauthorized)
+}
+
+fn require_scope(request: &Request, required: Scope) -> request::Outcome<(), ()> {
+    match request_scope(request) {
+        Ok(scope) if scope.allows(required) => rocket::Outcome::Success(()),
+        Ok(_) => rocket::Outcome::Failure((Status::Forbidden, ())),
+        Err(status) => rocket::Outcome::Failure((status, ())),
     }
 }
 
-fn get_bots() -> Vec<Watcher> {
-    let compose = Compose::get();
-    let bots: Vec<Watcher> = compose
-        .services
-        .into_iter()
-        .filter_map(|(name, elt)| {
-            if let Some(labels) = elt.labels.clone() {
-                if labels
-                    .into_iter()
-                    .find(|label| *label == WANAPLAY_SERVICE_LABEL.to_string())
-                    .is_some()
-                {
-                    let mut watcher = Watcher::from(elt);
-                    watcher.name = name.clone();
-                    let output = Command::new("docker")
-                        .arg("-H")
-                        .arg("unix:///var/run/docker.sock")
-                        .arg("ps")
-                        .arg("--filter")
-                        .arg(format!("name={}", name.clone()))
-                        .arg("--format")
-                        .arg("{{.Status}}")
-                        .output()
-                        .expect("failed to execute process");
-                    if !output.stdout.is_empty() {
-                        watcher.status =
-                            String::from_utf8(output.stdout).unwrap().trim().to_string();
-                    }
-                    return Some(watcher);
-                }
-            }
-            None
-        })
-        .collect();
-    bots
+/// Guard of the routes only reading bots and bookings.
+struct Reader;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Reader {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        require_scope(request, Scope::Read).map(|_| Reader)
+    }
+}
+
+/// Guard of the routes changing bots or bookings.
+struct Admin;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        require_scope(request, Scope::Admin).map(|_| Admin)
+    }
 }
 
-#[get("/bots")]
-fn get_all_bots() -> Json<Vec<Watcher>> {
-    Json(get_bots())
+#[get("/openapi.json")]
+fn openapi_document() -> Json<serde_json::Value> {
+    Json(openapi())
+}
+
+#[get("/bots?<page>&<per_page>")]
+fn get_all_bots(
+    page: Option<&RawStr>,
+    per_page: Option<&RawStr>,
+    backend: State<Backend>,
+    _auth: Reader,
+) -> Result<Json<Page<Watcher>>, ApiError> {
+    let bots = get_watchers(backend.as_ref()).map_err(ApiError::internal)?;
+    paginate(bots, page, per_page)
 }
 
 #[get("/bots/<id>")]
-fn get_bot(id: String) -> Option<Json<Watcher>> {
-    get_bots()
-        .into_iter()
-        .find(|bot| bot.name == id)
-        .map(|bot| Json(bot))
+fn get_bot(id: String, backend: State<Backend>, _auth: Reader) -> Result<Json<Watcher>, ApiError> {
+    match get_watcher(backend.as_ref(), &id) {
+        Ok(Some(bot)) => Ok(Json(bot)),
+        Ok(None) => Err(ApiError::bot_not_found(&id)),
+        Err(err) => Err(ApiError::internal(err)),
+    }
 }
 
 #[delete("/bots/<id>")]
-fn remove_bot(id: String) -> Status {
-    let bot = get_bots().into_iter().find(|bot| bot.name == id).unwrap();
-    let mut compose = Compose::get();
-    let removed = compose.remove_service(&id);
-    match removed {
-        Ok(_) => {
-            if bot.status == "Created" {
-                compose.update();
-                return Status::NoContent;
-            } else {
-                let output = Command::new("docker")
-                    .arg("-H")
-                    .arg("unix:///var/run/docker.sock")
-                    .arg("service")
-                    .arg("rm")
-                    .arg(format!("wanaplay_{}", id.clone()))
-                    .output()
-                    .expect("failed to execute process");
-                match output.status.success() {
-                    true => {
-                        compose.update();
-                        return Status::NoContent;
-                    }
-                    false => Status::InternalServerError,
-                }
-            }
-        }
-        Err(_) => Status::NotFound,
+fn remove_bot(id: String, backend: State<Backend>, _auth: Admin) -> Result<Status, ApiError> {
+    match get_watcher(backend.as_ref(), &id) {
+        Ok(Some(bot)) => match remove_watcher(backend.as_ref(), &bot) {
+            Ok(_) => Ok(Status::NoContent),
+            Err(err) => Err(ApiError::internal(err)),
+        },
+        Ok(None) => Err(ApiError::bot_not_found(&id)),
+        Err(err) => Err(ApiError::internal(err)),
     }
 }
 
 #[post("/bots", format = "json", data = "<watcher>")]
 fn new_bot(
     watcher: Json<Watcher>,
-) -> Result<status::Created<Json<Watcher>>, status::BadRequest<Json<ErrorContainer>>> {
-    match get_bot(watcher.name.clone()) {
-        Some(_) => Err(status::BadRequest(Some(Json(ErrorContainer::new(vec![
-            "watcher already exists".to_string(),
-        ]))))),
-        None => {
-            let mut compose = Compose::get();
-            let watcher_result = watcher.clone();
-            compose.add_service(watcher.name.clone(), Service::from(watcher));
-            compose.update();
-            Ok(status::Created(
-                format!("/bots/{}", watcher_result.name.clone()),
-                Some(Json(watcher_result)),
+    backend: State<Backend>,
+    _auth: Admin,
+) -> Result<status::Created<Json<Watcher>>, ApiError> {
+    validate(&watcher)?;
+    match get_watcher(backend.as_ref(), &watcher.name) {
+        Ok(None) => (),
+        Ok(Some(_)) => {
+            return Err(ApiError::new(
+                Status::Conflict,
+                format!("bot {} already exists", watcher.name),
             ))
         }
+        Err(err) => return Err(ApiError::internal(err)),
+    }
+    match add_watcher(watcher.into_inner()) {
+        Err(err) => Err(ApiError::internal(err)),
+        Ok(watcher) => Ok(status::Created(
+            format!("/api/{}/bots/{}", API_VERSION, watcher.name),
+            Some(Json(watcher)),
+        )),
     }
 }
 
 #[put("/bots/<id>", format = "json", data = "<watcher>")]
-fn update_bot(id: String, watcher: Json<Watcher>) -> Status {
-    let bot = get_bot(id.clone());
-    if bot.is_some() {
-        if id == watcher.name {
-            let mut compose = Compose::get();
-            compose.remove_service(&watcher.name).unwrap();
-            compose.add_service(watcher.name.clone(), Service::from(watcher));
-            compose.update();
-            Status::Ok
-        } else {
-            Status::Conflict
+fn update_bot(
+    id: String,
+    watcher: Json<Watcher>,
+    backend: State<Backend>,
+    _auth: Admin,
+) -> Result<Status, ApiError> {
+    validate(&watcher)?;
+    match get_watcher(backend.as_ref(), &id) {
+        Ok(Some(_)) if id == watcher.name => {
+            match update_watcher(backend.as_ref(), watcher.into_inner()) {
+                Ok(_) => Ok(Status::Ok),
+                Err(err) => Err(ApiError::internal(err)),
+            }
         }
-    } else {
-        Status::NotFound
+        Ok(Some(_)) => Err(ApiError::new(
+            Status::Conflict,
+            format!("bot {} can't be renamed to {}", id, watcher.name),
+        )),
+        Ok(None) => Err(ApiError::bot_not_found(&id)),
+        Err(err) => Err(ApiError::internal(err)),
+    }
+}
+
+fn backend_action<F>(
+    id: String,
+    backend: State<Backend>,
+    action: F,
+) -> Result<Json<Watcher>, ApiError>
+where
+    F: FnOnce(&dyn WatcherBackend, &Watcher) -> wanaplay_booker::Result<()>,
+{
+    match get_watcher(backend.as_ref(), &id) {
+        Ok(Some(bot)) => {
+            action(backend.as_ref(), &bot).map_err(ApiError::bad_request)?;
+            match get_watcher(backend.as_ref(), &id) {
+                Ok(Some(bot)) => Ok(Json(bot)),
+                Ok(None) => Err(ApiError::bot_not_found(&id)),
+                Err(err) => Err(ApiError::internal(err)),
+            }
+        }
+        Ok(None) => Err(ApiError::bot_not_found(&id)),
+        Err(err) => Err(ApiError::internal(err)),
+    }
+}
+
+#[post("/bots/<id>/start")]
+fn start_bot(id: String, backend: State<Backend>, _auth: Admin) -> Result<Json<Watcher>, ApiError> {
+    backend_action(id, backend, |backend, bot| backend.start(bot))
+}
+
+fn stored_action<F>(
+    id: String,
+    backend: State<Backend>,
+    action: F,
+) -> Result<Json<Watcher>, ApiError>
+where
+    F: FnOnce(&dyn WatcherBackend, &str) -> wanaplay_booker::Result<Watcher>,
+{
+    match get_watcher(backend.as_ref(), &id) {
+        Ok(Some(_)) => action(backend.as_ref(), &id)
+            .map(Json)
+            .map_err(ApiError::bad_request),
+        Ok(None) => Err(ApiError::bot_not_found(&id)),
+        Err(err) => Err(ApiError::internal(err)),
     }
 }
 
-#[post("/bots/actions/deploy")]
-fn deploy() -> Result<status::Created<()>, status::BadRequest<Json<ErrorContainer>>> {
-    let output = Command::new("docker")
-        .arg("-H")
-        .arg("unix:///var/run/docker.sock")
-        .arg("stack")
-        .arg("deploy")
-        .arg("-c")
-        .arg("docker-compose.yml")
-        .arg("wanaplay")
-        .output()
-        .expect("failed to execute process");
-    match output.status.success() {
-        true => Ok(status::Created("/bots".to_string(), None)),
-        false => Err(status::BadRequest(Some(Json(ErrorContainer::new(vec![
-            String::from_utf8(output.stderr).expect("Not UTF-8"),
-        ]))))),
+#[post("/bots/<id>/pause")]
+fn pause_bot(id: String, backend: State<Backend>, _auth: Admin) -> Result<Json<Watcher>, ApiError> {
+    stored_action(id, backend, pause_watcher)
+}
+
+#[post("/bots/<id>/resume")]
+fn resume_bot(
+    id: String,
+    backend: State<Backend>,
+    _auth: Admin,
+) -> Result<Json<Watcher>, ApiError> {
+    stored_action(id, backend, resume_watcher)
+}
+
+#[post("/bots/<id>/skip?<date>")]
+fn skip_bot(
+    id: String,
+    date: Option<&RawStr>,
+    backend: State<Backend>,
+    _auth: Admin,
+) -> Result<Json<Watcher>, ApiError> {
+    let period: SkipPeriod = query("date", date, "YYYY-MM-DD or YYYY-MM-DD..YYYY-MM-DD")?;
+    stored_action(id, backend, |backend, name| {
+        skip_watcher(backend, name, period)
+    })
+}
+
+#[post("/bots/<id>/stop")]
+fn stop_bot(id: String, backend: State<Backend>, _auth: Admin) -> Result<Json<Watcher>, ApiError> {
+    backend_action(id, backend, |backend, bot| backend.stop(bot))
+}
+
+#[post("/deployments")]
+fn deploy(backend: State<Backend>, _auth: Admin) -> Result<status::Created<()>, ApiError> {
+    match deploy_watchers(backend.as_ref()) {
+        Ok(_) => Ok(status::Created(format!("/api/{}/bots", API_VERSION), None)),
+        Err(err) => Err(ApiError::internal(err)),
     }
 }
 
-#[get("/bookings")]
-fn get_all_bookings() -> Json<Vec<Booking>> {
-    let bookings = get_bookings();
-    Json(bookings)
-}
-
-#[get("/time_slots?<date>")]
-fn get_time_slots(date: &RawStr) -> Json<Vec<String>> {
-    let date_obj = date.as_str().parse::<NaiveDate>().unwrap();
-    let client = get_logged_client().unwrap();
-    let response = client
-        .post(wanaplay_route("reservation/planning2").as_str())
-        .form(&[("date", date_obj.format("%Y-%m-%d").to_string())])
-        .send()
-        .unwrap();
-    let document = Document::from_read(response).unwrap();
-    let time_slots = document
-        .find(Class("creneauLibre"))
-        .map(|node| {
-            let slot = node
-                .children()
-                .next()
-                .unwrap()
-                .children()
-                .next()
-                .unwrap()
-                .text()
-                + ":00";
-            let slot_time = slot.parse::<NaiveTime>().unwrap();
-            slot_time
-        })
-        .collect::<HashSet<_>>();
-    let mut time_slots_vec = time_slots.iter().collect::<Vec<_>>();
-    time_slots_vec.sort();
-    dbg!(&time_slots_vec);
-    Json(
-        time_slots_vec
+#[get("/bookings?<page>&<per_page>")]
+fn get_all_bookings(
+    page: Option<&RawStr>,
+    per_page: Option<&RawStr>,
+    session: State<Session>,
+    _auth: Reader,
+) -> Result<Json<Page<Booking>>, ApiError> {
+    let client = logged_client(&session)?;
+    let bookings = fetch_bookings(&client).map_err(ApiError::upstream)?;
+    paginate(bookings, page, per_page)
+}
+
+/// Starts of the slots of the date with at least a free court.
+#[get("/slots/<date>")]
+fn slots(
+    date: String,
+    session: State<Session>,
+    cache: State<PlanningCache>,
+    _auth: Reader,
+) -> Result<Json<Vec<DateTime<FixedOffset>>>, ApiError> {
+    let date = path_date(&date)?;
+    let client = logged_client(&session)?;
+    let cells = cache.cells(&client, date).map_err(ApiError::upstream)?;
+    Ok(Json(
+        free_times(&cells)
             .iter()
-            .map(|d| d.format("%H:%M").to_string())
+            .map(|time| club_time(date.and_time(*time)))
             .collect(),
-    )
+    ))
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct CourtWithId {
-    court_number: u8,
-    booking_id: String,
-}
-
-#[get("/available_courts?<datetime>")]
-fn get_available_courts(datetime: &RawStr) -> Json<Vec<CourtWithId>> {
-    dbg!(&datetime);
-    let date_obj = datetime.as_str().parse::<NaiveDateTime>().unwrap();
-    dbg!(&date_obj);
-    let client = get_logged_client().unwrap();
-    let response = client
-        .post(wanaplay_route("reservation/planning2").as_str())
-        .form(&[("date", date_obj.format("%Y-%m-%d").to_string())])
-        .send()
-        .unwrap();
-    let document = Document::from_read(response).unwrap();
-    let courts = document
-        .find(Class("creneauLibre"))
-        .filter(|node| {
-            node.children()
-                .next()
-                .unwrap()
-                .children()
-                .next()
-                .unwrap()
-                .text()
-                == date_obj.format("%H:%M").to_string()
-        })
-        .map(|node| {
-            let id = node
-                .attr("onclick")
-                .unwrap()
-                .split("idTspl=")
-                .collect::<Vec<_>>()[1]
-                .replace("\"", "");
-            let book_response = client
-                .get(
-                    wanaplay_route(
-                        ("reservation/takeReservationShow?idTspl=".to_string() + &id).as_ref(),
-                    )
-                    .as_str(),
-                )
-                .send()
-                .unwrap();
-            let book_doc = Document::from_read(book_response).unwrap();
-            let resa_form = book_doc
-                .find(Attr("action", "/reservation/takeReservationConfirm"))
-                .next()
-                .unwrap();
-            let terrain_node = resa_form
-                .find(Name("p"))
-                .find(|node| node.text().contains("Terrain"))
-                .unwrap();
-            let court = terrain_node.clone().children().nth(2).unwrap().text();
-            let re = Regex::new(r"Court (\d)").unwrap();
-            let matches = re.captures(&court).unwrap();
-            CourtWithId {
-                court_number: matches.get(1).unwrap().as_str().parse::<u8>().unwrap(),
-                booking_id: id.clone(),
-            }
-        })
-        .collect::<Vec<_>>();
-    dbg!(&courts);
-    Json(courts)
-}
-
-#[post("/bookings/<id>?<date>")]
-fn book(id: String, date: &RawStr) -> Status {
-    let date_obj = date.as_str().parse::<NaiveDate>().unwrap();
-    let client = get_logged_client().unwrap();
-    let user_infos = get_user_infos(&client, &id);
-    match user_infos {
-        Ok(user_infos) => {
-            do_booking(&client, &user_infos, &id, &date_obj);
-            Status::Created
+/// `start` is one of the slots listed for its date, any UTC offset is accepted.
+#[get("/slots/<start>/courts")]
+fn slot_courts(
+    start: String,
+    session: State<Session>,
+    cache: State<PlanningCache>,
+    _auth: Reader,
+) -> Result<Json<Vec<CourtWithId>>, ApiError> {
+    let start = match DateTime::parse_from_rfc3339(&start) {
+        Ok(start) if court_times().contains(&to_club_time(&start).time()) => {
+            to_club_time(&start).naive_local()
+        }
+        _ => {
+            return Err(ApiError::bad_request(format!(
+                "{} is not the start of a slot, expected an RFC 3339 date time",
+                start
+            )))
         }
-        Err(_) => Status::BadRequest,
+    };
+    let client = logged_client(&session)?;
+    let cells = cache
+        .cells(&client, start.date())
+        .map_err(ApiError::upstream)?;
+    get_courts(&client, free_slot_ids(&cells, start.time()))
+        .map(Json)
+        .map_err(ApiError::upstream)
+}
+
+/// Courts of every slot from `from`, today by default, to `to`, a week later by default.
+#[get("/planning?<from>&<to>")]
+fn planning(
+    from: Option<&RawStr>,
+    to: Option<&RawStr>,
+    session: State<Session>,
+    cache: State<PlanningCache>,
+    _auth: Reader,
+) -> Result<Json<Planning>, ApiError> {
+    let from = optional_query("from", from, "YYYY-MM-DD")?
+        .unwrap_or_else(|| to_club_time(&Utc::now()).date().naive_local());
+    let to = optional_query("to", to, "YYYY-MM-DD")?.unwrap_or(from + Duration::days(6));
+    if to < from || to - from >= Duration::days(MAX_PLANNING_DAYS) {
+        return Err(ApiError::bad_request(format!(
+            "to should be after from, at most {} days later",
+            MAX_PLANNING_DAYS - 1
+        )));
+    }
+    let client = logged_client(&session)?;
+    fetch_planning_range(&client, Some(&cache), from, to)
+        .map(Json)
+        .map_err(ApiError::upstream)
+}
+
+/// Books the slot, answers the booking once wanaplay lists it, a conflict when someone
+/// else got the court first.
+#[post("/bookings", format = "json", data = "<booking>")]
+fn book(
+    booking: Json<NewBooking>,
+    session: State<Session>,
+    cache: State<PlanningCache>,
+    _auth: Admin,
+) -> Result<status::Created<Json<Booking>>, ApiError> {
+    let start = to_club_time(&booking.start);
+    if !court_times().contains(&start.time()) {
+        return Err(ApiError::bad_request(format!(
+            "{} is not a court time",
+            start.format("%H:%M:%S")
+        )));
     }
+    let date = start.date().naive_local();
+    let client = logged_client(&session)?;
+    let booked = book_slot(&client, &booking.slot_id, &date);
+    // even a failed booking may have changed the planning
+    cache.invalidate(date);
+    match booked {
+        Ok(Some(booking)) => Ok(status::Created(
+            format!("/api/{}/bookings/{}", API_VERSION, booking.id),
+            Some(Json(booking)),
+        )),
+        Ok(None) => Err(ApiError::new(
+            Status::Conflict,
+            format!("slot {} was booked by someone else", booking.slot_id),
+        )),
+        Err(err) => Err(ApiError::upstream(err)),
+    }
+}
+
+fn calendar(ics: String) -> Content<String> {
+    Content(ContentType::new("text", "calendar"), ics)
 }
 
+#[get("/bookings/<id>/invite.ics")]
+fn get_booking_invite(
+    id: String,
+    session: State<Session>,
+    _auth: Reader,
+) -> Result<Content<String>, ApiError> {
+    let client = logged_client(&session)?;
+    let booking = find_booking(&client, &id)?;
+    let participants = get_booking_participants(&client, &id).unwrap_or_default();
+    Ok(calendar(invite(
+        InviteMethod::Request,
+        &booking,
+        &participants,
+        &Club::from_env(),
+        &InviteConfig::from_env(),
+    )))
+}
+
+/// Cancels the booking and answers the cancellation invite to send the partners.
 #[delete("/bookings/<id>")]
-fn remove_booking(id: String) -> Status {
-    let client = get_logged_client().unwrap();
-    let exists = get_bookings()
-        .iter()
-        .find(|booking| booking.id == id)
-        .is_some();
-    if exists {
-        client
-            .get(
-                wanaplay_route(
-                    format!(
-                        "reservation/modifyReservationBase?idTspl={}&user_action=delete",
-                        id
-                    )
-                    .as_str(),
-                )
-                .as_str(),
-            )
-            .send()
-            .unwrap();
-        match get_bookings().iter().find(|booking| booking.id == id) {
-            Some(_) => Status::BadRequest,
-            None => Status::NoContent,
-        }
-    } else {
-        Status::NotFound
+fn remove_booking(
+    id: String,
+    session: State<Session>,
+    cache: State<PlanningCache>,
+    _auth: Admin,
+) -> Result<Content<String>, ApiError> {
+    let client = logged_client(&session)?;
+    let booking = find_booking(&client, &id)?;
+    let participants = get_booking_participants(&client, &id).unwrap_or_default();
+    let cancelled = cancel_booking(&client, &id);
+    cache.invalidate(booking.date());
+    match cancelled {
+        Err(err) => Err(ApiError::upstream(err)),
+        Ok(_) => Ok(calendar(invite(
+            InviteMethod::Cancel,
+            &booking,
+            &participants,
+            &Club::from_env(),
+            &InviteConfig::from_env(),
+        ))),
     }
 }
 
+/// Checks the signature of a cancellation link of a notification.
+fn verify_cancel_link(
+    id: &str,
+    expires: Option<&RawStr>,
+    signature: Option<&RawStr>,
+) -> Result<(), ApiError> {
+    let expires: i64 = query("expires", expires, "a timestamp")?;
+    let signature: String = query("signature", signature, "a signature")?;
+    let signer = ActionSigner::from_env().map_err(ApiError::internal)?;
+    match signer.verify(
+        CANCEL_ACTION,
+        id,
+        expires,
+        &signature,
+        Utc::now().timestamp(),
+    ) {
+        true => Ok(()),
+        false => Err(ApiError::new(
+            Status::Forbidden,
+            "the cancellation link is invalid or expired",
+        )),
+    }
+}
+
+/// Page of a cancellation link, it only asks for a confirmation so that link previews
+/// and mail scanners opening it don't cancel.
+#[get("/bookings/<id>/cancel?<expires>&<signature>")]
+fn cancel_link(
+    id: String,
+    expires: Option<&RawStr>,
+    signature: Option<&RawStr>,
+    session: State<Session>,
+) -> Result<Html<String>, ApiError> {
+    verify_cancel_link(&id, expires, signature)?;
+    let booking = find_booking(&logged_client(&session)?, &id)?;
+    Ok(Html(format!(
+        "<form method=\"post\"><p>Cancel court {} on {}?</p><button>cancel</button></form>",
+        booking.court_number,
+        booking.start.format("%a %d/%m %H:%M")
+    )))
+}
+
+#[post("/bookings/<id>/cancel?<expires>&<signature>")]
+fn confirm_cancel_link(
+    id: String,
+    expires: Option<&RawStr>,
+    signature: Option<&RawStr>,
+    session: State<Session>,
+    cache: State<PlanningCache>,
+) -> Result<Html<String>, ApiError> {
+    verify_cancel_link(&id, expires, signature)?;
+    let client = logged_client(&session)?;
+    let booking = find_booking(&client, &id)?;
+    let cancelled = cancel_booking(&client, &id);
+    cache.invalidate(booking.date());
+    cancelled.map_err(ApiError::upstream)?;
+    Ok(Html(format!(
+        "<p>Court {} on {} cancelled.</p>",
+        booking.court_number,
+        booking.start.format("%a %d/%m %H:%M")
+    )))
+}
+
+/// Offset of the wanaplay clock, as the booker measures it before opening.
+#[get("/diagnostics/clock")]
+fn clock(session: State<Session>, _auth: Reader) -> Result<Json<ClockEstimate>, ApiError> {
+    let client = logged_client(&session)?;
+    estimate_clock(&client, DEFAULT_CLOCK_SAMPLES)
+        .map(Json)
+        .map_err(ApiError::upstream)
+}
+
+/// Errors raised by rocket itself, e.g. a failed guard or a malformed body, in the
+/// same shape as the route errors.
+fn catcher_error(status: Status, request: &Request) -> Json<ErrorContainer> {
+    Json(ErrorContainer::new(vec![format!(
+        "{} {}: {}",
+        request.method(),
+        request.uri(),
+        status.reason.to_lowercase()
+    )]))
+}
+
+#[catch(400)]
+fn bad_request(request: &Request) -> Json<ErrorContainer> {
+    catcher_error(Status::BadRequest, request)
+}
+
+#[catch(401)]
+fn unauthorized(request: &Request) -> Json<ErrorContainer> {
+    catcher_error(Status::Unauthorized, request)
+}
+
+#[catch(403)]
+fn forbidden(request: &Request) -> Json<ErrorContainer> {
+    catcher_error(Status::Forbidden, request)
+}
+
+#[catch(404)]
+fn not_found(request: &Request) -> Json<ErrorContainer> {
+    catcher_error(Status::NotFound, request)
+}
+
+#[catch(422)]
+fn unprocessable_entity(request: &Request) -> Json<ErrorContainer> {
+    catcher_error(Status::UnprocessableEntity, request)
+}
+
+#[catch(500)]
+fn internal_error(request: &Request) -> Json<ErrorContainer> {
+    catcher_error(Status::InternalServerError, request)
+}
+
 fn main() {
-    for env_var in vec!["compose_file_path", "wanaplay_login", "wanaplay_password"] {
-        if env::var(env_var).is_err() {
-            println!("environment variable {} should be set", env_var);
+    let session = match get_credentials() {
+        Ok(credentials) => Session::new(credentials),
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let backend = match backend_from_env() {
+        Ok(backend) => backend,
+        Err(err) => {
+            println!("{}", err);
             std::process::exit(1);
         }
+    };
+    let api_keys = match ApiKeys::from_env() {
+        Ok(api_keys) => api_keys,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    if backend.is_embedded() {
+        if let Err(err) = deploy_watchers(backend.as_ref()) {
+            eprintln!("unable to start bots: {}", err);
+        }
     }
     rocket::ignite()
+        .manage(backend)
+        .manage(api_keys)
+        .manage(session)
+        .manage(PlanningCache::from_env())
         .mount(
-            "/",
+            &format!("/api/{}", API_VERSION),
             routes![
+                openapi_document,
                 get_all_bots,
                 get_bot,
                 new_bot,
                 remove_bot,
                 deploy,
                 update_bot,
+                start_bot,
+                pause_bot,
+                resume_bot,
+                skip_bot,
+                stop_bot,
                 get_all_bookings,
+                get_booking_invite,
                 remove_booking,
-                get_time_slots,
-                get_available_courts,
+                cancel_link,
+                confirm_cancel_link,
+                slots,
+                slot_courts,
+                planning,
                 book,
+                clock,
             ],
         )
+        .register(catchers![
+            bad_request,
+            unauthorized,
+            forbidden,
+            not_found,
+            unprocessable_entity,
+            internal_error,
+        ])
         .launch();
 }
@@ -1,16 +1,23 @@
 extern crate chrono;
 extern crate env_logger;
 use chrono::prelude::*;
+use chrono::Duration;
 use std::env;
 use std::thread;
-use std::time;
 extern crate failure;
 extern crate select;
 pub type Error = failure::Error;
 pub type Result<T> = std::result::Result<T, Error>;
-use itertools::join;
+use wanaplay_booker::auth::ActionSigner;
+use wanaplay_booker::club::{to_club_time, Club};
+use wanaplay_booker::notifier::{notifier_from_env, Event, EventAction, EventKind, Notifier};
+use wanaplay_booker::schedule::{
+    reminder_rules_from_env, upcoming_reminders, Reminder, ReminderRule,
+};
 use wanaplay_booker::*;
 
+const DEFAULT_REFRESH_MINUTES: i64 = 60;
+
 fn main() {
     env_logger::init();
     if let Err(err) = run() {
@@ -21,52 +28,102 @@ fn main() {
     }
 }
 
+/// Now on the club wall clock, the clock of the reminders.
+fn now() -> NaiveDateTime {
+    match env::var("fake_date") {
+        Ok(fake_date) => to_club_time(&fake_date.parse::<DateTime<FixedOffset>>().unwrap()),
+        Err(_) => to_club_time(&Utc::now()),
+    }
+    .naive_local()
+}
+
+/// Whether the booker is alone on the court, unknown when the participants can't be read.
+fn unpartnered(client: &reqwest::Client, booking: &Booking) -> Result<bool> {
+    Ok(get_booking_participants(client, &booking.id)?.len() <= 1)
+}
+
+fn reminder_event(reminder: &Reminder, club: &Club) -> Result<Event> {
+    let client = get_logged_client()?;
+    let mut message = reminder.message();
+    if let ReminderRule::LastChanceToCancel { .. } = reminder.rule {
+        let deadline = reminder.bookings[0].local_start() - club.cancellation_notice;
+        message += &format!(", free until {}", deadline.format("%a %d/%m %H:%M"));
+    }
+    for booking in reminder.bookings.iter() {
+        match unpartnered(&client, booking) {
+            Ok(true) => {
+                message += &format!(
+                    "\nonly one participant on {} at {}",
+                    booking.date().format("%a %d/%m"),
+                    booking.court_time()
+                )
+            }
+            Ok(false) => (),
+            Err(err) => eprintln!("unable to get participants of {}: {}", booking.id, err),
+        }
+    }
+    let mut event = Event::new(EventKind::Reminder, message);
+    if let (ReminderRule::LastChanceToCancel { .. }, Ok(proxy_url), Ok(signer)) = (
+        &reminder.rule,
+        env::var("proxy_url"),
+        ActionSigner::from_env(),
+    ) {
+        event.actions = reminder
+            .bookings
+            .iter()
+            .map(|booking| {
+                EventAction::cancel_booking(
+                    &proxy_url,
+                    &signer,
+                    booking,
+                    format!(
+                        "cancel {} at {}",
+                        booking.date().format("%a %d/%m"),
+                        booking.court_time()
+                    ),
+                )
+            })
+            .collect();
+    }
+    Ok(event)
+}
+
 fn run() -> Result<()> {
-    let bot_token = env::var("bot_token")?;
-    let chat_id = env::var("chat_id")?;
-    let client = reqwest::Client::new();
+    let notifier = notifier_from_env()?;
+    let club = Club::from_env();
+    let rules = reminder_rules_from_env()?;
+    let refresh = Duration::minutes(
+        env::var("reminder_refresh_minutes")
+            .ok()
+            .and_then(|minutes| minutes.parse().ok())
+            .unwrap_or(DEFAULT_REFRESH_MINUTES),
+    );
+    let mut checked_until = now();
     loop {
-        let now: DateTime<Local> = match env::var("fake_date") {
-            Ok(fake_date) => fake_date.parse::<DateTime<Local>>().unwrap(),
-            Err(_) => Local::now(),
-        };
+        let now = now();
         println!("loop {:?}", now);
-        if now.hour() == 9 {
-            let bookings = get_bookings();
-            let today_bookings = bookings
-                .into_iter()
-                .filter(|booking| booking.date == now.naive_local().date())
-                .collect::<Vec<_>>();
-            if !today_bookings.is_empty() {
-                let bookings_number = today_bookings.len();
-                println!("found {} bookings for today", bookings_number);
-                let details = join(
-                    today_bookings
-                        .iter()
-                        .map(|booking| booking.court_time.clone()),
-                    " and ",
-                );
-                let params = [
-                    ("chat_id", &chat_id),
-                    (
-                        "text",
-                        &format!(
-                            "{} bookings scheduled for today at {}",
-                            bookings_number, details
-                        )
-                        .to_string(),
-                    ),
-                ];
-                client
-                    .post(format!("https://api.telegram.org/bot{}/sendMessage", bot_token).as_str())
-                    .form(&params)
-                    .send()?;
+        let bookings = get_bookings();
+        let reminders = upcoming_reminders(&rules, &bookings, checked_until, &club);
+        for reminder in reminders.iter().take_while(|reminder| reminder.at <= now) {
+            println!(
+                "{:?} reminder for {} bookings",
+                reminder.rule,
+                reminder.bookings.len()
+            );
+            // a reminder that can't be sent is lost, the next ones may still be
+            if let Err(err) =
+                reminder_event(reminder, &club).and_then(|event| notifier.notify(&event))
+            {
+                eprintln!("unable to send the {:?} reminder: {}", reminder.rule, err);
             }
-            println!("sleep for 1d");
-            thread::sleep(time::Duration::from_secs(24 * 60 * 60));
-        } else {
-            println!("sleep for 1h");
-            thread::sleep(time::Duration::from_secs(60 * 60));
         }
+        checked_until = now;
+        let wake_up = reminders
+            .iter()
+            .map(|reminder| reminder.at)
+            .find(|at| *at > now)
+            .map_or(now + refresh, |at| at.min(now + refresh));
+        println!("sleep until {:?}", wake_up);
+        thread::sleep((wake_up - now).to_std().unwrap_or_default());
     }
 }
@@ -0,0 +1,617 @@
+extern crate chrono;
+extern crate env_logger;
+extern crate reqwest;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate failure;
+use chrono::prelude::*;
+use chrono::Duration;
+use failure::bail;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::env;
+use std::thread;
+use std::time;
+pub type Error = failure::Error;
+pub type Result<T> = std::result::Result<T, Error>;
+use wanaplay_booker::backend::{backend_from_env, WatcherBackend};
+use wanaplay_booker::booker::{Schedule, WatcherKind};
+use wanaplay_booker::club::Club;
+use wanaplay_booker::ics::{invite, InviteConfig, InviteMethod};
+use wanaplay_booker::watcher::*;
+use wanaplay_booker::*;
+
+const TELEGRAM_API_URL: &str = "https://api.telegram.org";
+const POLLING_TIMEOUT_SECONDS: u64 = 30;
+const WEEK_DAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+const HELP: &str = "
+accept - get the invite of a booking
+add - create a bot
+book - make a booking
+bookings - display all bookings
+bots - display all bots
+cancel - cancel a booking
+delete - delete a bot
+deploy - start all the created bots
+help - display this message
+";
+
+#[derive(Debug, Deserialize)]
+struct TelegramResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+    callback_query: Option<CallbackQuery>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    message_id: i64,
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackQuery {
+    id: String,
+    message: Option<Message>,
+    data: Option<String>,
+}
+
+/// Same `{"a": action, "d": data}` shape as the reminder's cancel buttons.
+#[derive(Debug, Serialize, Deserialize)]
+struct Callback {
+    a: String,
+    d: Value,
+}
+
+struct Button {
+    text: String,
+    action: &'static str,
+    data: Value,
+}
+
+impl Button {
+    fn new(text: String, action: &'static str, data: Value) -> Self {
+        Button { text, action, data }
+    }
+}
+
+fn keyboard(buttons: Vec<Button>, per_row: usize) -> Value {
+    let rows = buttons
+        .chunks(per_row)
+        .map(|row| {
+            row.iter()
+                .map(|button| {
+                    json!({
+                        "text": button.text,
+                        "callback_data": json!({"a": button.action, "d": button.data}).to_string(),
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    json!({ "inline_keyboard": rows })
+}
+
+struct Bot {
+    client: reqwest::Client,
+    api_url: String,
+    token: String,
+    backend: Box<dyn WatcherBackend>,
+}
+
+impl Bot {
+    fn from_env() -> Result<Self> {
+        Ok(Bot {
+            client: reqwest::Client::builder()
+                .timeout(time::Duration::from_secs(POLLING_TIMEOUT_SECONDS + 10))
+                .build()?,
+            api_url: env::var("telegram_api_url").unwrap_or_else(|_| TELEGRAM_API_URL.to_string()),
+            token: env::var("bot_token")?,
+            backend: backend_from_env()?,
+        })
+    }
+
+    fn method_url(&self, method: &str) -> String {
+        format!("{}/bot{}/{}", self.api_url, self.token, method)
+    }
+
+    fn parse<T: DeserializeOwned>(mut response: reqwest::Response) -> Result<T> {
+        let response: TelegramResponse<T> = response.json()?;
+        match (response.ok, response.result) {
+            (true, Some(result)) => Ok(result),
+            (_, _) => bail!(
+                "telegram error: {}",
+                response.description.unwrap_or_default()
+            ),
+        }
+    }
+
+    fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let response = self
+            .client
+            .post(self.method_url(method).as_str())
+            .json(&params)
+            .send()?;
+        Self::parse(response)
+    }
+
+    fn get_updates(&self, offset: i64) -> Result<Vec<Update>> {
+        self.call(
+            "getUpdates",
+            json!({"offset": offset, "timeout": POLLING_TIMEOUT_SECONDS}),
+        )
+    }
+
+    fn send_message(&self, chat_id: i64, text: &str) -> Result<()> {
+        let _: Value = self.call("sendMessage", json!({"chat_id": chat_id, "text": text}))?;
+        Ok(())
+    }
+
+    fn send_html(&self, chat_id: i64, html: &str) -> Result<()> {
+        let _: Value = self.call(
+            "sendMessage",
+            json!({"chat_id": chat_id, "text": html, "parse_mode": "html"}),
+        )?;
+        Ok(())
+    }
+
+    fn send_keyboard(
+        &self,
+        chat_id: i64,
+        text: &str,
+        buttons: Vec<Button>,
+        per_row: usize,
+    ) -> Result<()> {
+        let _: Value = self.call(
+            "sendMessage",
+            json!({"chat_id": chat_id, "text": text, "reply_markup": keyboard(buttons, per_row)}),
+        )?;
+        Ok(())
+    }
+
+    fn send_document(&self, chat_id: i64, file_name: &str, content: String) -> Result<()> {
+        let form = reqwest::multipart::Form::new()
+            .text("chat_id", chat_id.to_string())
+            .part(
+                "document",
+                reqwest::multipart::Part::text(content).file_name(file_name.to_string()),
+            );
+        let response = self
+            .client
+            .post(self.method_url("sendDocument").as_str())
+            .multipart(form)
+            .send()?;
+        let _: Value = Self::parse(response)?;
+        Ok(())
+    }
+
+    fn answer_callback(&self, callback_id: &str, text: &str) -> Result<()> {
+        let _: Value = self.call(
+            "answerCallbackQuery",
+            json!({"callback_query_id": callback_id, "text": text}),
+        )?;
+        Ok(())
+    }
+
+    fn delete_message(&self, chat_id: i64, message_id: i64) -> Result<()> {
+        let _: Value = self.call(
+            "deleteMessage",
+            json!({"chat_id": chat_id, "message_id": message_id}),
+        )?;
+        Ok(())
+    }
+}
+
+fn status<T>(result: Result<T>, action: &str) -> String {
+    match result {
+        Ok(_) => format!("ok for {}", action),
+        Err(err) => {
+            eprintln!("{}: {}", action, err);
+            format!("ko for {}", action)
+        }
+    }
+}
+
+fn bookings_md(bookings: &[Booking]) -> String {
+    if bookings.is_empty() {
+        return "no bookings found".to_string();
+    }
+    bookings
+        .iter()
+        .map(|booking| {
+            format!(
+                "{} at {} |   {}  \n",
+                booking.date().format("%a %d/%m"),
+                booking.court_time(),
+                booking.court_number
+            )
+        })
+        .collect()
+}
+
+fn bots_md(bots: &[Watcher]) -> String {
+    if bots.is_empty() {
+        return "no bots found".to_string();
+    }
+    bots.iter()
+        .map(|bot| {
+            format!(
+                "{:<19} | {} {:?}\n",
+                bot.name,
+                if bot.status.is_deployed() {
+                    " ☑ "
+                } else {
+                    " ☐ "
+                },
+                bot.status
+            )
+        })
+        .collect()
+}
+
+fn booking_label(booking: &Booking) -> String {
+    format!(
+        "{} at {}",
+        booking.date().format("%a %d/%m"),
+        booking.court_time()
+    )
+}
+
+fn bookings_selection(action: &'static str) -> Vec<Button> {
+    get_bookings()
+        .iter()
+        .map(|booking| Button::new(booking_label(booking), action, json!(booking.id)))
+        .collect()
+}
+
+fn handle_command(bot: &Bot, chat_id: i64, command: &str) -> Result<()> {
+    match command {
+        "/bookings" => bot.send_html(
+            chat_id,
+            &format!(
+                "<pre>\n     Booking       | Court #\n ----------------- | --------\n{}</pre>",
+                bookings_md(&get_bookings())
+            ),
+        ),
+        "/bots" => bot.send_html(
+            chat_id,
+            &format!(
+                "<pre>\n       Name         | Status\n ------------------ | ------\n{}</pre>",
+                bots_md(&get_watchers(bot.backend.as_ref())?)
+            ),
+        ),
+        "/deploy" => bot.send_message(
+            chat_id,
+            &status(deploy_watchers(bot.backend.as_ref()), "deploy"),
+        ),
+        "/add" => bot.send_keyboard(
+            chat_id,
+            "choose a day",
+            WEEK_DAYS
+                .iter()
+                .map(|day| Button::new(day.to_string(), "add_1", json!(day.to_lowercase())))
+                .collect(),
+            3,
+        ),
+        "/delete" => bot.send_keyboard(
+            chat_id,
+            "choose a bot to delete",
+            get_watchers(bot.backend.as_ref())?
+                .into_iter()
+                .map(|watcher| Button::new(watcher.name.clone(), "delete", json!(watcher.name)))
+                .collect(),
+            2,
+        ),
+        "/cancel" => bot.send_keyboard(
+            chat_id,
+            "choose a booking to cancel",
+            bookings_selection("cancel"),
+            2,
+        ),
+        "/accept" => bot.send_keyboard(
+            chat_id,
+            "choose a court to get invite",
+            bookings_selection("accept"),
+            2,
+        ),
+        "/book" => {
+            let today = Local::today().naive_local();
+            let this_end =
+                today + Duration::days(6 - today.weekday().num_days_from_monday() as i64);
+            let next_end = this_end + Duration::days(7);
+            let weeks = vec![
+                ("This week", today, this_end),
+                ("Next week", this_end.succ(), next_end),
+                (
+                    "After next week",
+                    next_end.succ(),
+                    today + Duration::days(14),
+                ),
+            ];
+            bot.send_keyboard(
+                chat_id,
+                "choose a week",
+                weeks
+                    .into_iter()
+                    .map(|(label, from, to)| {
+                        Button::new(
+                            label.to_string(),
+                            "book_1",
+                            json!({"from": from.to_string(), "to": to.to_string()}),
+                        )
+                    })
+                    .collect(),
+                2,
+            )
+        }
+        "/help" | "/start" => bot.send_message(chat_id, HELP),
+        _ => bot.send_message(chat_id, "Sorry, I didn't understand that command."),
+    }
+}
+
+fn str_data(data: &Value) -> Result<String> {
+    match data.as_str() {
+        Some(data) => Ok(data.to_string()),
+        None => bail!("unexpected callback data {}", data),
+    }
+}
+
+fn date_data(data: &Value, key: &str) -> Result<NaiveDate> {
+    match data.get(key).and_then(Value::as_str) {
+        Some(date) => Ok(date.parse::<NaiveDate>()?),
+        None => bail!("unexpected callback data {}", data),
+    }
+}
+
+/// Handles an inline keyboard choice, returning the message to display.
+fn handle_callback(bot: &Bot, chat_id: i64, callback: Callback) -> Result<Option<String>> {
+    match callback.a.as_str() {
+        "add_1" => {
+            let day = str_data(&callback.d)?;
+            bot.send_keyboard(
+                chat_id,
+                "choose a time slot",
+                court_times()
+                    .iter()
+                    .map(|time| {
+                        Button::new(
+                            time.format("%H:%M").to_string(),
+                            "add_2",
+                            json!(format!("bot_{}_{}", day, time.format("%H_%M"))),
+                        )
+                    })
+                    .collect(),
+                6,
+            )?;
+            Ok(None)
+        }
+        "add_2" => {
+            let name = str_data(&callback.d)?;
+            let parts = name.split('_').collect::<Vec<_>>();
+            if parts.len() != 4 {
+                bail!("unexpected bot name {}", name);
+            }
+            let watcher = Watcher {
+                id: 0,
+                name: name.clone(),
+                status: WatcherStatus::Draft,
+                court_time: NaiveTime::parse_from_str(
+                    &format!("{}:{}", parts[2], parts[3]),
+                    "%H:%M",
+                )?,
+                fallback_times: vec![],
+                schedule: Schedule::Weekly {
+                    week_day: parts[1]
+                        .parse()
+                        .map_err(|_| format_err!("{} is not a valid week day", parts[1]))?,
+                },
+                kind: WatcherKind::Opening,
+                enabled: true,
+                skip: vec![],
+                created_at: None,
+                updated_at: None,
+                last_outcome: None,
+                next_attempt: None,
+            };
+            Ok(Some(status(add_watcher(watcher), &format!("add {}", name))))
+        }
+        "delete" => {
+            let name = str_data(&callback.d)?;
+            let removed = match get_watcher(bot.backend.as_ref(), &name)? {
+                Some(watcher) => remove_watcher(bot.backend.as_ref(), &watcher),
+                None => Err(format_err!("watcher {} not found", name)),
+            };
+            Ok(Some(status(removed, &format!("delete {}", name))))
+        }
+        "cancel" => {
+            let id = str_data(&callback.d)?;
+            let label = get_bookings()
+                .iter()
+                .find(|booking| booking.id == id)
+                .map(booking_label)
+                .unwrap_or_else(|| id.clone());
+            let cancelled = get_logged_client().and_then(|client| cancel_booking(&client, &id));
+            Ok(Some(status(cancelled, &format!("cancel {}", label))))
+        }
+        "accept" => {
+            let id = str_data(&callback.d)?;
+            let sent = match get_bookings().into_iter().find(|booking| booking.id == id) {
+                Some(booking) => get_logged_client().and_then(|client| {
+                    let participants = get_booking_participants(&client, &id)?;
+                    let ics = invite(
+                        InviteMethod::Request,
+                        &booking,
+                        &participants,
+                        &Club::from_env(),
+                        &InviteConfig::from_env(),
+                    );
+                    bot.send_document(chat_id, "invite.squash.ics", ics)
+                }),
+                None => Err(format_err!("booking {} not found", id)),
+            };
+            Ok(Some(status(sent, &format!("accept {}", id))))
+        }
+        "book_1" => {
+            let mut date = date_data(&callback.d, "from")?;
+            let to = date_data(&callback.d, "to")?;
+            let mut buttons = vec![];
+            while date <= to {
+                buttons.push(Button::new(
+                    date.format("%A").to_string(),
+                    "book_2",
+                    json!(date.to_string()),
+                ));
+                date = date.succ();
+            }
+            bot.send_keyboard(chat_id, "choose a day", buttons, 3)?;
+            Ok(None)
+        }
+        "book_2" => {
+            let date = str_data(&callback.d)?.parse::<NaiveDate>()?;
+            let client = get_logged_client()?;
+            bot.send_keyboard(
+                chat_id,
+                "choose a time slot",
+                get_time_slots(&client, &date)?
+                    .iter()
+                    .map(|time| {
+                        Button::new(
+                            time.format("%H:%M").to_string(),
+                            "book_3",
+                            json!(date.and_time(*time).format("%Y-%m-%dT%H:%M:%S").to_string()),
+                        )
+                    })
+                    .collect(),
+                5,
+            )?;
+            Ok(None)
+        }
+        "book_3" => {
+            let datetime = str_data(&callback.d)?.parse::<NaiveDateTime>()?;
+            let client = get_logged_client()?;
+            bot.send_keyboard(
+                chat_id,
+                "choose a court",
+                get_available_courts(&client, &datetime)?
+                    .into_iter()
+                    .map(|court| {
+                        Button::new(
+                            court.court_number.to_string(),
+                            "book_f",
+                            json!({"id": court.booking_id, "date": datetime.date().to_string()}),
+                        )
+                    })
+                    .collect(),
+                4,
+            )?;
+            Ok(None)
+        }
+        "book_f" => {
+            let date = date_data(&callback.d, "date")?;
+            let id = match callback.d.get("id").and_then(Value::as_str) {
+                Some(id) => id.to_string(),
+                None => bail!("unexpected callback data {}", callback.d),
+            };
+            let booked =
+                get_logged_client().and_then(|client| match book_slot(&client, &id, &date)? {
+                    Some(_) => Ok(()),
+                    None => bail!("slot {} was not booked", id),
+                });
+            Ok(Some(status(booked, &format!("book for {} ({})", date, id))))
+        }
+        action => bail!("unknown action: {}", action),
+    }
+}
+
+fn handle_update(bot: &Bot, update: Update) -> Result<()> {
+    if let Some(message) = update.message {
+        if let Some(text) = message.text {
+            let command = text.split_whitespace().next().unwrap_or_default();
+            let command = command.split('@').next().unwrap_or_default();
+            println!("command {} from {}", command, message.chat.id);
+            handle_command(bot, message.chat.id, command)?;
+        }
+    }
+    if let Some(query) = update.callback_query {
+        let message = match query.message {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        let chat_id = message.chat.id;
+        let callback = serde_json::from_str::<Callback>(&query.data.unwrap_or_default());
+        println!("callback {:?} from {}", callback, chat_id);
+        match callback
+            .map_err(Error::from)
+            .and_then(|callback| handle_callback(bot, chat_id, callback))
+        {
+            Ok(text) => {
+                let text = text.unwrap_or_default();
+                if !text.is_empty() {
+                    bot.send_message(chat_id, &text)?;
+                }
+                bot.answer_callback(&query.id, &text)?;
+                bot.delete_message(chat_id, message.message_id)?;
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                bot.answer_callback(&query.id, &format!("unknown action: {}", err))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    env_logger::init();
+    if let Err(err) = run() {
+        for cause in err.iter_chain() {
+            eprintln!("{}", cause);
+        }
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let bot = Bot::from_env()?;
+    let mut offset = 0;
+    loop {
+        let updates = match bot.get_updates(offset) {
+            Ok(updates) => updates,
+            Err(err) => {
+                eprintln!("unable to get updates: {}", err);
+                thread::sleep(time::Duration::from_secs(5));
+                continue;
+            }
+        };
+        for update in updates {
+            offset = update.update_id + 1;
+            if let Err(err) = handle_update(&bot, update) {
+                eprintln!("{}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+extern crate structopt;
+use structopt::StructOpt;
+extern crate failure;
+use failure::bail;
+pub type Error = failure::Error;
+pub type Result<T> = std::result::Result<T, Error>;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+use wanaplay_booker::vault::{zeroize_string, Vault, VaultAccount, VaultKey};
+use wanaplay_booker::WanaplayPassword;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "wanaplay-vault",
+    about = " Manage the encrypted wanaplay credentials, unlocked with vault_passphrase or vault_key_file"
+)]
+struct Opt {
+    /// vault file, defaults to vault_path
+    #[structopt(short = "p", long = "path", parse(from_os_str))]
+    path: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// add or replace an account, the password is read from stdin
+    #[structopt(name = "set")]
+    Set {
+        account: String,
+        #[structopt(short = "l", long = "login")]
+        login: String,
+        /// only keep the SHA-1 digest wanaplay expects, not the password
+        #[structopt(long = "sha1_only")]
+        sha1_only: bool,
+    },
+    #[structopt(name = "remove")]
+    Remove { account: String },
+    #[structopt(name = "list")]
+    List,
+}
+
+fn main() {
+    if let Err(err) = run() {
+        for cause in err.iter_chain() {
+            eprintln!("{}", cause);
+        }
+        std::process::exit(1);
+    }
+}
+
+fn read_password() -> Result<String> {
+    let mut password = String::new();
+    io::stdin().lock().read_line(&mut password)?;
+    let trimmed = password
+        .trim_end_matches(|c| c == '\r' || c == '\n')
+        .to_string();
+    zeroize_string(&mut password);
+    if trimmed.is_empty() {
+        bail!("the password should be given on stdin");
+    }
+    Ok(trimmed)
+}
+
+fn run() -> Result<()> {
+    let opt = Opt::from_args();
+    let path = match opt.path.or_else(Vault::path_from_env) {
+        Some(path) => path,
+        None => bail!("either --path or environment variable vault_path should be set"),
+    };
+    let mut vault = Vault::open(&path, VaultKey::from_env()?)?;
+    match opt.command {
+        Command::Set {
+            account,
+            login,
+            sha1_only,
+        } => {
+            let password = read_password()?;
+            let credentials = match sha1_only {
+                true => VaultAccount {
+                    login,
+                    password: None,
+                    password_sha1: Some(WanaplayPassword::Plain(password).crypted()),
+                },
+                false => VaultAccount {
+                    login,
+                    password: Some(password),
+                    password_sha1: None,
+                },
+            };
+            vault.set(&account, credentials);
+            vault.save()?;
+            println!("account {} saved in {}", account, path.display());
+        }
+        Command::Remove { account } => match vault.remove(&account) {
+            Some(_) => {
+                vault.save()?;
+                println!("account {} removed from {}", account, path.display());
+            }
+            None => bail!("account {} not found in {}", account, path.display()),
+        },
+        Command::List => {
+            for (account, credentials) in vault.accounts() {
+                let kept = match credentials.password {
+                    Some(_) => "password",
+                    None => "sha1 digest",
+                };
+                println!("{}\t{}\t{}", account, credentials.login, kept);
+            }
+        }
+    }
+    Ok(())
+}
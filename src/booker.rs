@@ -0,0 +1,577 @@
+use crate::club::club_time;
+use crate::governor::Governed;
+use crate::notifier::{BookingOutcome, Event, Notifier};
+use crate::planning::fetch_cells;
+use crate::runtime::Session;
+use crate::watcher::Watcher;
+use crate::{book_slot, court_times, wanaplay_route, Result};
+use chrono::prelude::*;
+use chrono::Duration;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::result::Result as StdResult;
+use std::str::FromStr;
+use std::thread;
+use std::time;
+
+pub const OPENING_TIMEOUT_MINUTES: i64 = 30;
+/// Bookings open this many days ahead, at midnight.
+pub const BOOKING_HORIZON_DAYS: i64 = 15;
+/// How long before the opening the preparation starts, the login, the reservation form
+/// and the clock samples take a few seconds and the first poll must not wait for them.
+const PREPARATION_MINUTES: i64 = 10;
+
+/// Dates not to book, written `2026-11-05` or `2026-12-21..2027-01-03` (both included).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkipPeriod {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl SkipPeriod {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.from <= date && date <= self.to
+    }
+}
+
+impl FromStr for SkipPeriod {
+    type Err = crate::Error;
+
+    fn from_str(period: &str) -> Result<Self> {
+        let mut dates = period.trim().splitn(2, "..");
+        let from = dates.next().unwrap_or_default();
+        let from = from
+            .parse::<NaiveDate>()
+            .map_err(|_| format_err!("{} is not a valid date, expected YYYY-MM-DD", from))?;
+        let to = match dates.next() {
+            Some(to) => to
+                .parse::<NaiveDate>()
+                .map_err(|_| format_err!("{} is not a valid date, expected YYYY-MM-DD", to))?,
+            None => from,
+        };
+        if to < from {
+            bail!("{} ends before it starts", period);
+        }
+        Ok(SkipPeriod { from, to })
+    }
+}
+
+impl fmt::Display for SkipPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.from == self.to {
+            true => write!(f, "{}", self.from),
+            false => write!(f, "{}..{}", self.from, self.to),
+        }
+    }
+}
+
+impl Serialize for SkipPeriod {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl JsonSchema for SkipPeriod {
+    fn schema_name() -> String {
+        "SkipPeriod".to_string()
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        serde_json::from_value(json!({"type": "string", "example": "2026-12-21..2027-01-03"}))
+            .unwrap()
+    }
+}
+
+impl<'de> Deserialize<'de> for SkipPeriod {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> StdResult<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// When a watcher books: every week, or a single date written `{"date": "2026-11-05"}`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Schedule {
+    Once {
+        date: NaiveDate,
+    },
+    Weekly {
+        #[serde(with = "crate::serde_week_day")]
+        #[schemars(schema_with = "crate::serde_week_day::schema")]
+        week_day: Weekday,
+    },
+}
+
+const DEFAULT_SNIPER_INTERVAL_SECONDS: u64 = 60;
+const DEFAULT_SNIPER_BUDGET_MINUTES: i64 = 6 * 60;
+
+fn default_sniper_interval() -> u64 {
+    DEFAULT_SNIPER_INTERVAL_SECONDS
+}
+
+fn default_sniper_budget() -> i64 {
+    DEFAULT_SNIPER_BUDGET_MINUTES
+}
+
+/// Polls the planning for courts freed by cancellations, starting between the court
+/// time and `until`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Sniper {
+    #[serde(with = "crate::serde_court_time")]
+    #[schemars(schema_with = "crate::serde_court_time::schema")]
+    pub until: NaiveTime,
+    #[serde(default = "default_sniper_interval")]
+    pub interval_seconds: u64,
+    /// polling starts this long before the court time, when late cancellations happen
+    #[serde(default = "default_sniper_budget")]
+    pub budget_minutes: i64,
+}
+
+/// How a watcher books: at midnight when bookings open, or by sniping cancellations.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WatcherKind {
+    Opening,
+    Sniper(Sniper),
+}
+
+impl Default for WatcherKind {
+    fn default() -> Self {
+        WatcherKind::Opening
+    }
+}
+
+/// What a watcher tries to book.
+#[derive(Debug, Clone)]
+pub struct BookerParameters {
+    pub kind: WatcherKind,
+    pub schedule: Schedule,
+    pub court_time: NaiveTime,
+    pub fallback_times: Vec<NaiveTime>,
+    pub skip: Vec<SkipPeriod>,
+}
+
+impl BookerParameters {
+    pub fn is_skipped(&self, date: NaiveDate) -> bool {
+        self.skip.iter().any(|period| period.contains(date))
+    }
+}
+
+impl<'a> From<&'a Watcher> for BookerParameters {
+    fn from(watcher: &'a Watcher) -> Self {
+        BookerParameters {
+            kind: watcher.kind,
+            schedule: watcher.schedule,
+            court_time: watcher.court_time,
+            fallback_times: watcher.fallback_times.clone(),
+            skip: watcher.skip.clone(),
+        }
+    }
+}
+
+/// The date bookings open for at the next midnight following `now`.
+pub fn target_date(now: DateTime<Local>) -> NaiveDate {
+    (now + Duration::days(BOOKING_HORIZON_DAYS))
+        .date()
+        .naive_local()
+}
+
+fn local(date_time: NaiveDateTime) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&date_time).earliest()
+}
+
+/// When an opening watcher wakes up for `date`: before the planning opens, to log in,
+/// read the reservation form and the server clock without eating into the opening.
+fn wake_up(date: NaiveDate) -> DateTime<Local> {
+    planning_opening(date).with_timezone(&Local) - Duration::minutes(PREPARATION_MINUTES)
+}
+
+/// When a sniper starts polling for `date`: `budget_minutes` before the court time, but
+/// not before the planning opens.
+fn sniping_start(
+    parameters: &BookerParameters,
+    sniper: &Sniper,
+    date: NaiveDate,
+) -> Option<DateTime<Local>> {
+    let start =
+        local(date.and_time(parameters.court_time))? - Duration::minutes(sniper.budget_minutes);
+    Some(start.max(planning_opening(date).with_timezone(&Local)))
+}
+
+/// When wanaplay opens the planning of `date`: midnight at the club, 14 days before.
+pub fn planning_opening(date: NaiveDate) -> DateTime<FixedOffset> {
+    club_time((date - Duration::days(BOOKING_HORIZON_DAYS - 1)).and_hms(0, 0, 0))
+}
+
+/// Starts of the courts of `date` at the given times, at the club.
+pub(crate) fn court_starts(
+    date: NaiveDate,
+    court_times: &[NaiveTime],
+) -> Vec<DateTime<FixedOffset>> {
+    court_times
+        .iter()
+        .map(|time| club_time(date.and_time(*time)))
+        .collect()
+}
+
+/// Next attempt and the date it books, `None` once a one-shot date passed. Skipped dates
+/// are left out. Attempts start `PREPARATION_MINUTES` before the opening, or right away
+/// inside the opening window or when the date is already open. Snipers start their
+/// budget before the court time and run until the last court time they accept.
+pub fn next_attempt(
+    parameters: &BookerParameters,
+    now: DateTime<Local>,
+) -> Option<(DateTime<Local>, NaiveDate)> {
+    match (parameters.kind, parameters.schedule) {
+        (WatcherKind::Opening, Schedule::Weekly { week_day }) => {
+            let mut date = now.date();
+            loop {
+                let target = (date + Duration::days(BOOKING_HORIZON_DAYS)).naive_local();
+                if date.weekday() == week_day.pred() && !parameters.is_skipped(target) {
+                    let start = wake_up(target);
+                    if now < start {
+                        return Some((start, target));
+                    }
+                    if now < date.succ().and_hms(0, 0, 0) {
+                        return Some((now, target));
+                    }
+                }
+                date = date.succ();
+            }
+        }
+        (WatcherKind::Opening, Schedule::Once { date }) => {
+            if date < now.date().naive_local() || parameters.is_skipped(date) {
+                return None;
+            }
+            Some((wake_up(date).max(now), date))
+        }
+        (WatcherKind::Sniper(sniper), Schedule::Weekly { week_day }) => {
+            let mut date = now.date().naive_local();
+            loop {
+                if date.weekday() == week_day
+                    && !parameters.is_skipped(date)
+                    && now < local(date.and_time(sniper.until))?
+                {
+                    return Some((sniping_start(parameters, &sniper, date)?.max(now), date));
+                }
+                date = date.succ();
+            }
+        }
+        (WatcherKind::Sniper(sniper), Schedule::Once { date }) => {
+            if parameters.is_skipped(date) || now >= local(date.and_time(sniper.until))? {
+                return None;
+            }
+            Some((sniping_start(parameters, &sniper, date)?.max(now), date))
+        }
+    }
+}
+
+/// Shown instead of the planning of a date not open yet.
+pub(crate) const PLANNING_NOT_OPEN: &str = "Vous ne pouvez pas voir le planning";
+
+pub fn is_openned(client: &reqwest::Client, target_date: NaiveDate) -> Result<bool> {
+    println!("watch_openning {:?} at {:?}", target_date, Local::now());
+    let mut response = client
+        .post(wanaplay_route("reservation/planning2").as_str())
+        .form(&[("date", target_date.format("%Y-%m-%d").to_string())])
+        .governed_send()?;
+    Ok(!response.text()?.contains(PLANNING_NOT_OPEN))
+}
+
+/// Free courts of the day with their start time and booking id.
+pub fn find_free_slots(
+    client: &reqwest::Client,
+    target_date: NaiveDate,
+) -> Result<Vec<(NaiveTime, String)>> {
+    let slots = fetch_cells(client, target_date)?
+        .into_iter()
+        .filter(|cell| cell.free)
+        .filter_map(|cell| Some((cell.time, cell.slot_id?)))
+        .collect();
+    Ok(slots)
+}
+
+pub fn find_book_ids(
+    client: &reqwest::Client,
+    target_date: NaiveDate,
+    court_time: NaiveTime,
+) -> Result<Vec<String>> {
+    println!("finding ids for {:?} at {:?}", target_date, court_time);
+    let ids = find_free_slots(client, target_date)?
+        .into_iter()
+        .filter(|(time, _)| *time == court_time)
+        .map(|(_, id)| id)
+        .collect::<Vec<_>>();
+    println!("{:?}", ids);
+    Ok(ids)
+}
+
+/// Polls the planning of `target_date` until a court the sniper accepts frees up, then
+/// books it. Gives up at the last court time accepted or when `stopped` returns true.
+pub fn snipe(
+    session: &Session,
+    parameters: &BookerParameters,
+    sniper: &Sniper,
+    target_date: NaiveDate,
+    stopped: &dyn Fn() -> bool,
+) -> BookingOutcome {
+    let court_times = court_times()
+        .into_iter()
+        .filter(|time| *time >= parameters.court_time && *time <= sniper.until)
+        .collect::<Vec<_>>();
+    let deadline = local(target_date.and_time(sniper.until)).unwrap_or_else(Local::now);
+    while !stopped() && Local::now() < deadline {
+        println!("sniping {:?} at {:?}", target_date, Local::now());
+        let polled = session.client().and_then(|client| {
+            let slots = find_free_slots(&client, target_date)?;
+            Ok((client, slots))
+        });
+        match polled {
+            Ok((client, mut slots)) => {
+                slots.retain(|(time, _)| court_times.contains(time));
+                slots.sort_by_key(|(time, _)| *time);
+                for (court_time, id) in slots {
+                    match book_slot(&client, &id, &target_date) {
+                        Ok(Some(booking)) if court_time == parameters.court_time => {
+                            return BookingOutcome::Booked {
+                                start: booking.start,
+                                court_number: booking.court_number,
+                            }
+                        }
+                        Ok(Some(booking)) => {
+                            return BookingOutcome::FellBack {
+                                wanted_start: club_time(
+                                    target_date.and_time(parameters.court_time),
+                                ),
+                                start: booking.start,
+                                court_number: booking.court_number,
+                            }
+                        }
+                        Ok(None) => println!("{:?} was taken", id),
+                        Err(err) => eprintln!("unable to book {:?}: {}", id, err),
+                    }
+                }
+            }
+            Err(err) => eprintln!("unable to poll the planning: {}", err),
+        }
+        for _ in 0..sniper.interval_seconds {
+            if stopped() {
+                break;
+            }
+            thread::sleep(time::Duration::from_secs(1));
+        }
+    }
+    BookingOutcome::NothingFree {
+        starts: court_starts(target_date, &court_times),
+    }
+}
+
+/// Waits for the planning of `target_date` to open then books the first free court,
+/// trying the fallback times in order.
+pub fn attempt_booking(
+    client: &reqwest::Client,
+    parameters: &BookerParameters,
+    target_date: NaiveDate,
+) -> BookingOutcome {
+    let deadline = Local::now() + Duration::minutes(OPENING_TIMEOUT_MINUTES);
+    loop {
+        match is_openned(client, target_date) {
+            Ok(true) => break,
+            Ok(false) => (),
+            Err(err) => eprintln!("unable to watch opening: {}", err),
+        }
+        if Local::now() > deadline {
+            return BookingOutcome::NeverOpened { date: target_date };
+        }
+        thread::sleep(time::Duration::from_secs(2));
+    }
+    let court_times = std::iter::once(parameters.court_time)
+        .chain(parameters.fallback_times.iter().cloned())
+        .collect::<Vec<_>>();
+    let mut fell_back = false;
+    for court_time in court_times.iter() {
+        let mut ids = match find_book_ids(client, target_date, *court_time) {
+            Ok(ids) => ids,
+            Err(err) => {
+                eprintln!("unable to find ids for {}: {}", court_time, err);
+                vec![]
+            }
+        };
+        if ids.len() == 4 {
+            ids.swap(0, 1);
+        }
+        for id in ids {
+            match book_slot(client, &id, &target_date) {
+                Ok(Some(booking)) if !fell_back => {
+                    return BookingOutcome::Booked {
+                        start: booking.start,
+                        court_number: booking.court_number,
+                    }
+                }
+                Ok(Some(booking)) => {
+                    return BookingOutcome::FellBack {
+                        wanted_start: club_time(target_date.and_time(parameters.court_time)),
+                        start: booking.start,
+                        court_number: booking.court_number,
+                    }
+                }
+                Ok(None) => println!("{:?} was taken", id),
+                Err(err) => eprintln!("unable to book {:?}: {}", id, err),
+            }
+        }
+        // the other courts of the time were tried, the next time is a fallback
+        fell_back = true;
+    }
+    BookingOutcome::NothingFree {
+        starts: court_starts(target_date, &court_times),
+    }
+}
+
+pub fn notify(notifier: &dyn Notifier, outcome: BookingOutcome) {
+    println!("{}", outcome.message());
+    if let Err(err) = notifier.notify(&Event::from(outcome)) {
+        eprintln!("{}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opening(schedule: Schedule) -> BookerParameters {
+        BookerParameters {
+            kind: WatcherKind::Opening,
+            schedule,
+            court_time: NaiveTime::from_hms(19, 0, 0),
+            fallback_times: vec![],
+            skip: vec![],
+        }
+    }
+
+    fn utc(date_time: &str) -> DateTime<Local> {
+        date_time
+            .parse::<DateTime<Utc>>()
+            .unwrap()
+            .with_timezone(&Local)
+    }
+
+    fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(year, month, day)
+    }
+
+    #[test]
+    fn plannings_open_at_midnight_at_the_club_14_days_before() {
+        let opening = planning_opening(ymd(2024, 3, 20));
+        assert_eq!(opening.to_rfc3339(), "2024-03-06T00:00:00+01:00");
+        // summer time starts at 2:00 on 31 March 2024 and ends at 3:00 on 27 October
+        let openings = [
+            (ymd(2024, 4, 13), "2024-03-29T23:00:00+00:00"),
+            (ymd(2024, 4, 14), "2024-03-30T23:00:00+00:00"),
+            (ymd(2024, 4, 15), "2024-03-31T22:00:00+00:00"),
+            (ymd(2024, 11, 10), "2024-10-26T22:00:00+00:00"),
+            (ymd(2024, 11, 11), "2024-10-27T23:00:00+00:00"),
+        ];
+        for (date, opening) in openings.iter() {
+            let utc = planning_opening(*date).with_timezone(&Utc).to_rfc3339();
+            assert_eq!(&utc, opening, "{}", date);
+        }
+    }
+
+    #[test]
+    fn weekly_openings_wake_up_ten_minutes_before_the_planning_of_the_week_day_opens() {
+        let parameters = opening(Schedule::Weekly {
+            week_day: Weekday::Wed,
+        });
+        let now = utc("2024-03-04T12:00:00Z");
+        let (start, date) = next_attempt(&parameters, now).unwrap();
+        assert_eq!(date, ymd(2024, 3, 20));
+        assert_eq!(date.weekday(), Weekday::Wed);
+        assert_eq!(start, utc("2024-03-05T22:50:00Z"));
+        // inside the preparation, the attempt starts right away
+        let now = utc("2024-03-05T22:55:00Z");
+        assert_eq!(next_attempt(&parameters, now), Some((now, date)));
+    }
+
+    #[test]
+    fn opening_wake_ups_follow_the_club_summer_time() {
+        let parameters = opening(Schedule::Once {
+            date: ymd(2024, 4, 15),
+        });
+        let now = utc("2024-03-20T12:00:00Z");
+        assert_eq!(
+            next_attempt(&parameters, now),
+            Some((utc("2024-03-31T21:50:00Z"), ymd(2024, 4, 15)))
+        );
+        let parameters = opening(Schedule::Once {
+            date: ymd(2024, 4, 13),
+        });
+        assert_eq!(
+            next_attempt(&parameters, now),
+            Some((utc("2024-03-29T22:50:00Z"), ymd(2024, 4, 13)))
+        );
+    }
+
+    #[test]
+    fn past_one_shot_dates_are_not_attempted() {
+        let parameters = opening(Schedule::Once {
+            date: ymd(2024, 3, 20),
+        });
+        assert_eq!(next_attempt(&parameters, utc("2024-03-21T12:00:00Z")), None);
+    }
+
+    #[test]
+    fn skip_periods_are_a_date_or_an_inclusive_range() {
+        let day = "2024-03-20".parse::<SkipPeriod>().unwrap();
+        assert_eq!((day.from, day.to), (ymd(2024, 3, 20), ymd(2024, 3, 20)));
+        assert_eq!(day.to_string(), "2024-03-20");
+        let holidays = " 2024-12-21..2025-01-03".parse::<SkipPeriod>().unwrap();
+        assert_eq!(holidays.to_string(), "2024-12-21..2025-01-03");
+        assert!(!holidays.contains(ymd(2024, 12, 20)));
+        assert!(holidays.contains(ymd(2024, 12, 21)));
+        assert!(holidays.contains(ymd(2025, 1, 1)));
+        assert!(holidays.contains(ymd(2025, 1, 3)));
+        assert!(!holidays.contains(ymd(2025, 1, 4)));
+        for invalid in &["2024-13-01", "2024-03-20..", "2024-03-20..2024-03-19", ""] {
+            assert!(invalid.parse::<SkipPeriod>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn skipped_dates_are_not_attempted() {
+        let mut parameters = opening(Schedule::Weekly {
+            week_day: Weekday::Wed,
+        });
+        parameters.skip = vec!["2024-03-20".parse().unwrap()];
+        let now = utc("2024-03-04T12:00:00Z");
+        let (start, date) = next_attempt(&parameters, now).unwrap();
+        assert_eq!(date, ymd(2024, 3, 27));
+        assert_eq!(start, utc("2024-03-12T22:50:00Z"));
+
+        parameters.schedule = Schedule::Once {
+            date: ymd(2024, 3, 20),
+        };
+        assert_eq!(next_attempt(&parameters, now), None);
+    }
+
+    #[test]
+    fn skipped_ranges_crossing_the_target_move_to_the_week_after() {
+        let mut parameters = opening(Schedule::Weekly {
+            week_day: Weekday::Wed,
+        });
+        // covers the 20th and the 27th, from before the first opening
+        parameters.skip = vec!["2024-03-18..2024-03-28".parse().unwrap()];
+        let now = utc("2024-03-04T12:00:00Z");
+        let (start, date) = next_attempt(&parameters, now).unwrap();
+        assert_eq!(date, ymd(2024, 4, 3));
+        assert_eq!(start, utc("2024-03-19T22:50:00Z"));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::governor::{governor, is_overloaded};
+use crate::{wanaplay_route, Result};
+use chrono::{DateTime, Duration, Utc};
+use reqwest::header;
+use schemars::JsonSchema;
+use std::thread;
+use std::time;
+
+/// Samples taken for an estimate, spread over a bit more than a second so that one of
+/// them sees the `Date` header tick.
+pub const DEFAULT_CLOCK_SAMPLES: usize = 5;
+const SAMPLE_INTERVAL_MILLISECONDS: u64 = 270;
+
+/// A request to wanaplay and the date it answered with.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ClockSample {
+    pub sent: DateTime<Utc>,
+    pub round_trip_ms: i64,
+    /// `Date` header, whole seconds of the server clock
+    pub server_date: DateTime<Utc>,
+}
+
+impl ClockSample {
+    /// Smallest and largest offsets the sample allows: the server read its clock after
+    /// the request was sent and before the response came back, at most a second after
+    /// its header date.
+    fn bounds(&self) -> (Duration, Duration) {
+        let received = self.sent + Duration::milliseconds(self.round_trip_ms);
+        (
+            self.server_date - received,
+            self.server_date + Duration::seconds(1) - self.sent,
+        )
+    }
+}
+
+/// Server clock minus the local clock.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ClockEstimate {
+    pub offset_ms: i64,
+    /// the offset is at most this far from the truth
+    pub uncertainty_ms: i64,
+    /// fastest round-trip of the samples
+    pub round_trip_ms: i64,
+    pub measured_at: DateTime<Utc>,
+    pub samples: Vec<ClockSample>,
+}
+
+impl ClockEstimate {
+    pub fn offset(&self) -> Duration {
+        Duration::milliseconds(self.offset_ms)
+    }
+
+    /// Narrows the offset to the bounds every sample agrees on. Samples disagree when
+    /// the server clock jumped, the sample of the fastest round-trip is trusted then.
+    pub fn from_samples(samples: Vec<ClockSample>) -> Result<Self> {
+        let fastest = match samples.iter().min_by_key(|sample| sample.round_trip_ms) {
+            Some(fastest) => fastest.clone(),
+            None => bail!("no clock sample"),
+        };
+        let bounds = samples.iter().map(ClockSample::bounds);
+        let mut lowest = bounds.clone().map(|(lowest, _)| lowest).max().unwrap();
+        let mut highest = bounds.map(|(_, highest)| highest).min().unwrap();
+        if lowest > highest {
+            let (fastest_lowest, fastest_highest) = fastest.bounds();
+            lowest = fastest_lowest;
+            highest = fastest_highest;
+        }
+        Ok(ClockEstimate {
+            offset_ms: (lowest + (highest - lowest) / 2).num_milliseconds(),
+            uncertainty_ms: ((highest - lowest) / 2).num_milliseconds(),
+            round_trip_ms: fastest.round_trip_ms,
+            measured_at: Utc::now(),
+            samples,
+        })
+    }
+}
+
+pub fn sample_clock(client: &reqwest::Client) -> Result<ClockSample> {
+    let url = wanaplay_route("");
+    // the round-trip shouldn't count the wait for the governor
+    let permit = governor().acquire(&url);
+    let sent = Utc::now();
+    let response = client.head(url.as_str()).send();
+    let received = Utc::now();
+    permit.finish(match &response {
+        Ok(response) => is_overloaded(response.status()),
+        Err(_) => true,
+    });
+    let response = response?;
+    let date = match response.headers().get(header::DATE) {
+        Some(date) => date.to_str()?,
+        None => bail!("wanaplay didn't send its date"),
+    };
+    Ok(ClockSample {
+        sent,
+        round_trip_ms: (received - sent).num_milliseconds(),
+        server_date: DateTime::parse_from_rfc2822(date)?.with_timezone(&Utc),
+    })
+}
+
+/// Estimates the server clock from the `Date` headers of `samples` requests. Failed
+/// requests are left out, as long as one succeeds.
+pub fn estimate_clock(client: &reqwest::Client, samples: usize) -> Result<ClockEstimate> {
+    let mut taken = vec![];
+    let mut last_error = None;
+    for index in 0..samples {
+        if index > 0 {
+            thread::sleep(time::Duration::from_millis(SAMPLE_INTERVAL_MILLISECONDS));
+        }
+        match sample_clock(client) {
+            Ok(sample) => taken.push(sample),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    match (taken.is_empty(), last_error) {
+        (true, Some(err)) => Err(err),
+        _ => ClockEstimate::from_samples(taken),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// A request sent `sent_ms` after noon, answered with the server at `server_seconds`
+    /// after noon.
+    fn sample(sent_ms: i64, round_trip_ms: i64, server_seconds: i64) -> ClockSample {
+        let noon = Utc.ymd(2024, 3, 4).and_hms(12, 0, 0);
+        ClockSample {
+            sent: noon + Duration::milliseconds(sent_ms),
+            round_trip_ms,
+            server_date: noon + Duration::seconds(server_seconds),
+        }
+    }
+
+    #[test]
+    fn the_offset_is_narrowed_to_the_bounds_of_every_sample() {
+        // allows 4900ms to 6000ms, then 4600ms to 5700ms
+        let estimate =
+            ClockEstimate::from_samples(vec![sample(0, 100, 5), sample(300, 100, 5)]).unwrap();
+        assert_eq!(estimate.offset_ms, 5300);
+        assert_eq!(estimate.uncertainty_ms, 400);
+        assert_eq!(estimate.round_trip_ms, 100);
+        assert_eq!(estimate.samples.len(), 2);
+    }
+
+    #[test]
+    fn the_fastest_sample_is_trusted_when_samples_disagree() {
+        // allows 4800ms to 6000ms, then 7650ms to 8700ms after the server clock jumped
+        let estimate =
+            ClockEstimate::from_samples(vec![sample(0, 200, 5), sample(300, 50, 8)]).unwrap();
+        assert_eq!(estimate.offset_ms, 8175);
+        assert_eq!(estimate.uncertainty_ms, 525);
+        assert_eq!(estimate.round_trip_ms, 50);
+    }
+
+    #[test]
+    fn the_offset_is_the_server_clock_minus_the_local_clock() {
+        let behind = sample(0, 100, -3);
+        let estimate = ClockEstimate::from_samples(vec![behind.clone()]).unwrap();
+        assert_eq!(estimate.offset(), Duration::milliseconds(-2550));
+        let server_time = behind.sent + estimate.offset();
+        assert!(server_time >= behind.server_date);
+        assert!(server_time < behind.server_date + Duration::seconds(1));
+    }
+
+    #[test]
+    fn no_sample_is_no_estimate() {
+        assert!(ClockEstimate::from_samples(vec![]).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Weekday,
+};
+use std::env;
+
+const DEFAULT_CLUB_SLUG: &str = "espacesportifpontoise";
+const DEFAULT_CLUB_NAME: &str = "Espace Sportif Pontoise";
+const DEFAULT_CLUB_ADDRESS: &str = "19 Rue de Pontoise, 75005 Paris, France";
+const DEFAULT_CANCELLATION_NOTICE_HOURS: i64 = 24;
+
+#[derive(Debug, Clone)]
+pub struct Club {
+    pub slug: String,
+    pub name: String,
+    pub address: String,
+    /// cancelling later than this before the slot is charged
+    pub cancellation_notice: Duration,
+}
+
+impl Club {
+    pub fn from_env() -> Self {
+        Club {
+            slug: env::var("club_slug").unwrap_or_else(|_| DEFAULT_CLUB_SLUG.to_string()),
+            name: env::var("club_name").unwrap_or_else(|_| DEFAULT_CLUB_NAME.to_string()),
+            address: env::var("club_address").unwrap_or_else(|_| DEFAULT_CLUB_ADDRESS.to_string()),
+            cancellation_notice: Duration::hours(
+                env::var("club_cancellation_notice_hours")
+                    .ok()
+                    .and_then(|hours| hours.parse().ok())
+                    .unwrap_or(DEFAULT_CANCELLATION_NOTICE_HOURS),
+            ),
+        }
+    }
+
+    pub fn planning_route(&self) -> String {
+        format!("plannings/{}", self.slug)
+    }
+}
+
+/// Last Sunday of the month, when summer time starts in March and ends in October.
+fn last_sunday(year: i32, month: u32) -> NaiveDate {
+    let mut date = NaiveDate::from_ymd(year, month + 1, 1).pred();
+    while date.weekday() != Weekday::Sun {
+        date = date.pred();
+    }
+    date
+}
+
+/// Whether Europe/Paris is on summer time, from 01:00 UTC on the last Sunday of
+/// March to 01:00 UTC on the last Sunday of October.
+fn is_summer_time(utc: NaiveDateTime) -> bool {
+    utc >= last_sunday(utc.year(), 3).and_hms(1, 0, 0)
+        && utc < last_sunday(utc.year(), 10).and_hms(1, 0, 0)
+}
+
+fn paris_offset(utc: NaiveDateTime) -> FixedOffset {
+    match is_summer_time(utc) {
+        true => FixedOffset::east(2 * 3600),
+        false => FixedOffset::east(3600),
+    }
+}
+
+/// Wall clock time of the club, in Europe/Paris, with its UTC offset. Times repeated
+/// when summer time ends are taken as the first ones.
+pub fn club_time(local: NaiveDateTime) -> DateTime<FixedOffset> {
+    let offset = paris_offset(local - Duration::hours(2));
+    offset.from_local_datetime(&local).unwrap()
+}
+
+/// The same instant, as seen from the club.
+pub fn to_club_time<Tz: TimeZone>(instant: &DateTime<Tz>) -> DateTime<FixedOffset> {
+    let utc = instant.naive_utc();
+    paris_offset(utc).from_utc_datetime(&utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn utc(date_time: &str) -> DateTime<Utc> {
+        date_time.parse().unwrap()
+    }
+
+    fn local(date_time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_time, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn summer_time_starts_at_one_utc_on_the_last_sunday_of_march() {
+        assert_eq!(
+            to_club_time(&utc("2026-03-29T00:59:00Z")).to_rfc3339(),
+            "2026-03-29T01:59:00+01:00"
+        );
+        assert_eq!(
+            to_club_time(&utc("2026-03-29T01:00:00Z")).to_rfc3339(),
+            "2026-03-29T03:00:00+02:00"
+        );
+        assert_eq!(
+            club_time(local("2026-03-29 01:59")).to_rfc3339(),
+            "2026-03-29T01:59:00+01:00"
+        );
+        assert_eq!(
+            club_time(local("2026-03-29 03:00")).to_rfc3339(),
+            "2026-03-29T03:00:00+02:00"
+        );
+        // a week before, still winter time
+        assert_eq!(
+            club_time(local("2026-03-22 12:00")).to_rfc3339(),
+            "2026-03-22T12:00:00+01:00"
+        );
+    }
+
+    #[test]
+    fn summer_time_ends_at_one_utc_on_the_last_sunday_of_october() {
+        assert_eq!(
+            to_club_time(&utc("2026-10-25T00:59:00Z")).to_rfc3339(),
+            "2026-10-25T02:59:00+02:00"
+        );
+        assert_eq!(
+            to_club_time(&utc("2026-10-25T01:00:00Z")).to_rfc3339(),
+            "2026-10-25T02:00:00+01:00"
+        );
+        // 02:30 happens twice, the first one is taken
+        assert_eq!(
+            club_time(local("2026-10-25 02:30")).to_rfc3339(),
+            "2026-10-25T02:30:00+02:00"
+        );
+        assert_eq!(
+            club_time(local("2026-10-25 03:00")).to_rfc3339(),
+            "2026-10-25T03:00:00+01:00"
+        );
+        assert_eq!(
+            club_time(local("2026-10-18 12:00")).to_rfc3339(),
+            "2026-10-18T12:00:00+02:00"
+        );
+    }
+}
@@ -0,0 +1,389 @@
+use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use std::collections::HashMap;
+use std::env;
+use std::result::Result as StdResult;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_REQUESTS_PER_SECOND: f64 = 2.0;
+const DEFAULT_BURST: f64 = 4.0;
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 4;
+const BACKOFF_BASE_MILLISECONDS: u64 = 1000;
+const BACKOFF_MAX_MILLISECONDS: u64 = 60 * 1000;
+
+lazy_static! {
+    static ref GOVERNOR: Governor = Governor::from_env();
+}
+
+/// The governor every wanaplay request goes through.
+pub fn governor() -> &'static Governor {
+    &GOVERNOR
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Between half and all of the exponential backoff of the consecutive failures, picked
+/// by `jitter`, so that watchers failing together don't retry together.
+fn backoff(failures: u32, jitter: u64) -> Duration {
+    let backoff = BACKOFF_BASE_MILLISECONDS
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(BACKOFF_MAX_MILLISECONDS);
+    Duration::from_millis(backoff / 2 + jitter % (backoff / 2 + 1))
+}
+
+struct HostState {
+    /// requests that can be sent right away, refilled at the rate limit
+    tokens: f64,
+    refilled_at: Instant,
+    failures: u32,
+    blocked_until: Option<Instant>,
+}
+
+impl HostState {
+    /// How long until `count` requests can be sent, `None` when they can be right now.
+    fn wait(
+        &mut self,
+        count: f64,
+        now: Instant,
+        requests_per_second: f64,
+        burst: f64,
+        ignore_backoff: bool,
+    ) -> Option<Duration> {
+        let elapsed = now.duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * requests_per_second).min(burst);
+        self.refilled_at = now;
+        match self.blocked_until {
+            Some(until) if until > now && !ignore_backoff => Some(until - now),
+            _ if self.tokens >= count => None,
+            _ => Some(Duration::from_secs_f64(
+                (count - self.tokens) / requests_per_second,
+            )),
+        }
+    }
+}
+
+#[derive(Default)]
+struct GovernorState {
+    hosts: HashMap<String, HostState>,
+    in_flight: usize,
+}
+
+/// Keeps the requests polite: a rate limit with some burst per host, at most so many
+/// requests in flight, and a jittered backoff on a host after errors.
+///
+/// The limits hold within a process: the watchers of the in-process runtime share them,
+/// while every bot of the swarm, compose, systemd and process backends is a process of
+/// its own with its own budget, n bots can send n times the rate.
+pub struct Governor {
+    requests_per_second: f64,
+    burst: f64,
+    max_concurrent: usize,
+    state: Mutex<GovernorState>,
+    released: Condvar,
+    /// xorshift state of the backoff jitter
+    jitter: Mutex<u64>,
+}
+
+impl Governor {
+    pub fn new(requests_per_second: f64, burst: f64, max_concurrent: usize) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_nanos() as u64)
+            .unwrap_or_default();
+        Governor {
+            requests_per_second: requests_per_second.max(0.01),
+            burst: burst.max(1.0),
+            max_concurrent: max_concurrent.max(1),
+            state: Mutex::new(GovernorState::default()),
+            released: Condvar::new(),
+            // xorshift never leaves 0
+            jitter: Mutex::new(seed | 1),
+        }
+    }
+
+    /// Reads `wanaplay_requests_per_second` (2 by default), `wanaplay_burst` (4) and
+    /// `wanaplay_max_concurrent_requests` (4).
+    pub fn from_env() -> Self {
+        Governor::new(
+            env_or("wanaplay_requests_per_second", DEFAULT_REQUESTS_PER_SECOND),
+            env_or("wanaplay_burst", DEFAULT_BURST),
+            env_or(
+                "wanaplay_max_concurrent_requests",
+                DEFAULT_MAX_CONCURRENT_REQUESTS,
+            ),
+        )
+    }
+
+    /// Most requests that can be sent at once.
+    pub fn max_batch(&self) -> usize {
+        self.max_concurrent.min(self.burst as usize)
+    }
+
+    /// Waits for a turn to send a request to `url`. The permit should be kept until
+    /// the response arrived.
+    pub fn acquire(&'static self, url: &str) -> Permit {
+        self.acquire_many(url, 1).pop().unwrap()
+    }
+
+    /// Waits for `count` requests to `url` to be allowed at once, at most `max_batch`.
+    /// Taken one at a time, two batches could each hold part of the permits and wait
+    /// for the other forever.
+    pub fn acquire_many(&'static self, url: &str, count: usize) -> Vec<Permit> {
+        self.take(url, count, false)
+    }
+
+    /// Like `acquire_many` but ignoring the backoff of the host, for the requests at
+    /// the opening of a planning: a timeout just before midnight mustn't hold them for
+    /// up to a minute while the courts go. The rate limit still applies.
+    pub fn acquire_urgent(&'static self, url: &str, count: usize) -> Vec<Permit> {
+        self.take(url, count, true)
+    }
+
+    /// Like `acquire_urgent` for a single request.
+    pub fn acquire_urgent_one(&'static self, url: &str) -> Permit {
+        self.take(url, 1, true).pop().unwrap()
+    }
+
+    fn take(&'static self, url: &str, count: usize, urgent: bool) -> Vec<Permit> {
+        let count = count.min(self.max_batch()).max(1);
+        let host = host(url);
+        let mut state = self.state.lock().unwrap();
+        loop {
+            state = match self.try_take(&mut state, &host, count, urgent, Instant::now()) {
+                Ok(permits) => return permits,
+                Err(None) => self.released.wait(state).unwrap(),
+                Err(Some(wait)) => self.released.wait_timeout(state, wait).unwrap().0,
+            };
+        }
+    }
+
+    /// The permits if they can be given at `now`, else how long until they may be,
+    /// `None` when waiting for requests in flight to end.
+    fn try_take(
+        &'static self,
+        state: &mut GovernorState,
+        host: &str,
+        count: usize,
+        urgent: bool,
+        now: Instant,
+    ) -> StdResult<Vec<Permit>, Option<Duration>> {
+        let burst = self.burst;
+        let host_state = state.hosts.entry(host.to_string()).or_insert(HostState {
+            tokens: burst,
+            refilled_at: now,
+            failures: 0,
+            blocked_until: None,
+        });
+        match host_state.wait(count as f64, now, self.requests_per_second, burst, urgent) {
+            Some(wait) => Err(Some(wait)),
+            None if state.in_flight + count > self.max_concurrent => Err(None),
+            None => {
+                host_state.tokens -= count as f64;
+                state.in_flight += count;
+                Ok((0..count)
+                    .map(|_| Permit {
+                        governor: self,
+                        host: host.to_string(),
+                        finished: false,
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    fn next_jitter(&self) -> u64 {
+        let mut jitter = self.jitter.lock().unwrap();
+        *jitter ^= *jitter << 13;
+        *jitter ^= *jitter >> 7;
+        *jitter ^= *jitter << 17;
+        *jitter
+    }
+
+    fn release(&self, host: &str, failed: bool) {
+        self.release_at(host, failed, Instant::now())
+    }
+
+    fn release_at(&self, host: &str, failed: bool, now: Instant) {
+        let jitter = self.next_jitter();
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        if let Some(host_state) = state.hosts.get_mut(host) {
+            match failed {
+                true => {
+                    host_state.failures += 1;
+                    let backoff = backoff(host_state.failures, jitter);
+                    eprintln!("backing off {} for {:?}", host, backoff);
+                    host_state.blocked_until = Some(now + backoff);
+                }
+                false => host_state.failures = 0,
+            }
+        }
+        self.released.notify_all();
+    }
+}
+
+/// A request in flight, counted as successful when dropped without `finish`.
+pub struct Permit {
+    governor: &'static Governor,
+    host: String,
+    finished: bool,
+}
+
+impl Permit {
+    /// Ends the request, a failed one backs the host off.
+    pub fn finish(mut self, failed: bool) {
+        self.finished = true;
+        self.governor.release(&self.host, failed);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.governor.release(&self.host, false);
+        }
+    }
+}
+
+/// Whether the server asked to slow down or failed, worth backing off.
+pub fn is_overloaded(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+pub trait Governed {
+    /// Sends the request once the governor allows it.
+    fn governed_send(self) -> reqwest::Result<Response>;
+}
+
+impl Governed for RequestBuilder {
+    fn governed_send(self) -> reqwest::Result<Response> {
+        let url = self
+            .try_clone()
+            .and_then(|request| request.build().ok())
+            .map(|request| request.url().to_string())
+            .unwrap_or_default();
+        let permit = governor().acquire(&url);
+        let response = self.send();
+        let failed = match &response {
+            Ok(response) => is_overloaded(response.status()),
+            Err(_) => true,
+        };
+        permit.finish(failed);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://wanaplay.example/reservation/planning2";
+
+    fn governor(requests_per_second: f64, burst: f64, max_concurrent: usize) -> &'static Governor {
+        let governor = Governor::new(requests_per_second, burst, max_concurrent);
+        *governor.jitter.lock().unwrap() = 42;
+        Box::leak(Box::new(governor))
+    }
+
+    #[test]
+    fn tokens_refill_at_the_rate_up_to_the_burst() {
+        let start = Instant::now();
+        let mut host = HostState {
+            tokens: 0.0,
+            refilled_at: start,
+            failures: 0,
+            blocked_until: None,
+        };
+        assert_eq!(
+            host.wait(1.0, start, 2.0, 4.0, false),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            host.wait(1.0, start + Duration::from_millis(500), 2.0, 4.0, false),
+            None
+        );
+        // ten seconds refill 20 tokens, only 4 are kept
+        let later = start + Duration::from_secs(10);
+        assert_eq!(
+            host.wait(5.0, later, 2.0, 4.0, false),
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn requests_in_flight_are_limited() {
+        let governor = governor(100.0, 4.0, 2);
+        let now = Instant::now();
+        let mut state = governor.state.lock().unwrap();
+        let permits = governor
+            .try_take(&mut state, "host", 2, false, now)
+            .unwrap();
+        assert_eq!(
+            governor.try_take(&mut state, "host", 1, false, now).err(),
+            Some(None)
+        );
+        drop(state);
+        drop(permits);
+        let mut state = governor.state.lock().unwrap();
+        let permits = governor.try_take(&mut state, "host", 1, false, now);
+        // permits release the lock when dropped
+        drop(state);
+        assert!(permits.is_ok());
+    }
+
+    #[test]
+    fn backoff_is_between_half_and_all_of_the_exponential() {
+        let governor = governor(1.0, 1.0, 1);
+        for failures in 1..=20 {
+            let full = Duration::from_millis(
+                (BACKOFF_BASE_MILLISECONDS << (failures - 1).min(16)).min(BACKOFF_MAX_MILLISECONDS),
+            );
+            for _ in 0..100 {
+                let backoff = backoff(failures, governor.next_jitter());
+                assert!(backoff >= full / 2 && backoff <= full, "{:?}", backoff);
+            }
+        }
+    }
+
+    #[test]
+    fn urgent_requests_ignore_the_backoff_but_not_the_rate() {
+        let governor = governor(1.0, 2.0, 4);
+        let now = Instant::now();
+        let mut state = governor.state.lock().unwrap();
+        let permit = governor
+            .try_take(&mut state, "host", 1, false, now)
+            .unwrap();
+        drop(state);
+        let mut permit = permit.into_iter().next().unwrap();
+        permit.finished = true;
+        governor.release_at("host", true, now);
+        let mut state = governor.state.lock().unwrap();
+        let wait = governor.try_take(&mut state, "host", 1, false, now).err();
+        let backed_off = wait.unwrap().unwrap();
+        assert!(backed_off >= Duration::from_millis(500) && backed_off <= Duration::from_secs(1));
+        let urgent = governor.try_take(&mut state, "host", 1, true, now);
+        // the burst of 2 is spent
+        let over_burst = governor.try_take(&mut state, "host", 1, true, now).err();
+        drop(state);
+        assert!(urgent.is_ok());
+        assert_eq!(over_burst, Some(Some(Duration::from_secs(1))));
+    }
+
+    #[test]
+    fn hosts_are_read_from_urls() {
+        assert_eq!(host(URL), "wanaplay.example");
+        assert_eq!(host("not a url"), "");
+    }
+}
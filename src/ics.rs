@@ -0,0 +1,201 @@
+use crate::club::Club;
+use crate::{Booking, Participant, COURT_DURATION_MINUTES};
+use chrono::{Duration, Utc};
+use std::env;
+
+/// UTC times, calendars outside the club time zone show them right without a VTIMEZONE.
+const ICS_DATE_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const ICS_LINE_OCTETS: usize = 75;
+const DEFAULT_ALARM_MINUTES: i64 = 10;
+
+#[derive(Debug, Clone)]
+pub struct Partner {
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct InviteConfig {
+    pub organizer: Option<String>,
+    pub partners: Vec<Partner>,
+    pub alarm_minutes: i64,
+}
+
+impl InviteConfig {
+    /// `invite_partners` is a comma separated list of `Name:email`, the name being
+    /// the one displayed by wanaplay for the participant.
+    pub fn from_env() -> Self {
+        let partners = env::var("invite_partners")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|partner| {
+                let mut parts = partner.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(email)) => Some(Partner {
+                        name: name.trim().to_string(),
+                        email: email.trim().to_string(),
+                    }),
+                    _ => None,
+                }
+            })
+            .collect();
+        InviteConfig {
+            organizer: env::var("invite_organizer").ok(),
+            partners,
+            alarm_minutes: env::var("invite_alarm_minutes")
+                .ok()
+                .and_then(|minutes| minutes.parse().ok())
+                .unwrap_or(DEFAULT_ALARM_MINUTES),
+        }
+    }
+
+    fn partner_email(&self, name: &str) -> Option<&str> {
+        self.partners
+            .iter()
+            .find(|partner| partner.name.eq_ignore_ascii_case(name))
+            .map(|partner| partner.email.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InviteMethod {
+    Request,
+    Cancel,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// A parameter value such as `CN`: values can't hold `"`, the ones holding `:`, `;` or
+/// `,` are quoted (RFC 5545 section 3.2).
+fn parameter_value(value: &str) -> String {
+    let value = value.replace('"', "");
+    match value.contains(&[':', ';', ','][..]) {
+        true => format!("\"{}\"", value),
+        false => value,
+    }
+}
+
+/// Folds a content line longer than 75 octets, the continuation lines start with a
+/// space. Characters aren't split.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len());
+    let mut octets = 0;
+    for character in line.chars() {
+        if octets + character.len_utf8() > ICS_LINE_OCTETS {
+            folded.push_str("\r\n ");
+            octets = 1;
+        }
+        folded.push(character);
+        octets += character.len_utf8();
+    }
+    folded
+}
+
+pub fn invite(
+    method: InviteMethod,
+    booking: &Booking,
+    participants: &[Participant],
+    club: &Club,
+    config: &InviteConfig,
+) -> String {
+    let start = booking.start.with_timezone(&Utc);
+    let end = start + Duration::minutes(COURT_DURATION_MINUTES);
+    let now = Utc::now();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "PRODID:-//wanaplay-booker//EN".to_string(),
+        "VERSION:2.0".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+    ];
+    lines.push(match method {
+        InviteMethod::Request => "METHOD:REQUEST".to_string(),
+        InviteMethod::Cancel => "METHOD:CANCEL".to_string(),
+    });
+    lines.push("BEGIN:VEVENT".to_string());
+    lines.push(format!("UID:{}@wanaplay-booker", booking.id));
+    lines.push(format!("DTSTAMP:{}", now.format(ICS_DATE_TIME_FORMAT)));
+    lines.push(format!("DTSTART:{}", start.format(ICS_DATE_TIME_FORMAT)));
+    lines.push(format!("DTEND:{}", end.format(ICS_DATE_TIME_FORMAT)));
+    if let Some(organizer) = &config.organizer {
+        lines.push(format!(
+            "ORGANIZER;CN={}:mailto:{}",
+            parameter_value(organizer),
+            organizer
+        ));
+    }
+    for participant in participants {
+        if let Some(email) = config.partner_email(&participant.name) {
+            lines.push(format!(
+                "ATTENDEE;CN={};ROLE=REQ-PARTICIPANT;RSVP=TRUE:mailto:{}",
+                parameter_value(&participant.name),
+                email
+            ));
+        }
+    }
+    lines.push(format!(
+        "DESCRIPTION:Court {}{}",
+        booking.court_number,
+        escape(
+            &participants
+                .iter()
+                .map(|participant| format!("\n{}", participant.name))
+                .collect::<String>()
+        )
+    ));
+    lines.push(format!("LOCATION:{}", escape(&club.address)));
+    lines.push(format!("SUMMARY:Squash - {}", escape(&club.name)));
+    lines.push("TRANSP:OPAQUE".to_string());
+    match method {
+        InviteMethod::Request => {
+            lines.push("SEQUENCE:0".to_string());
+            lines.push("STATUS:CONFIRMED".to_string());
+            lines.push("BEGIN:VALARM".to_string());
+            lines.push("ACTION:DISPLAY".to_string());
+            lines.push("DESCRIPTION:Squash".to_string());
+            lines.push(format!("TRIGGER:-PT{}M", config.alarm_minutes));
+            lines.push("END:VALARM".to_string());
+        }
+        InviteMethod::Cancel => {
+            lines.push("SEQUENCE:1".to_string());
+            lines.push("STATUS:CANCELLED".to_string());
+        }
+    }
+    lines.push("END:VEVENT".to_string());
+    lines.push("END:VCALENDAR".to_string());
+    lines
+        .iter()
+        .map(|line| fold(line))
+        .collect::<Vec<_>>()
+        .join("\r\n")
+        + "\r\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_lines_are_folded_at_75_octets() {
+        let line = format!("LOCATION:{}", "é".repeat(60));
+        let folded = fold(&line);
+        let parts = folded.split("\r\n").collect::<Vec<_>>();
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|part| part.len() <= ICS_LINE_OCTETS));
+        assert!(parts[1].starts_with(' '));
+        assert_eq!(parts.concat().replacen(" ", "", 1), line);
+        assert_eq!(fold("SUMMARY:Squash"), "SUMMARY:Squash");
+    }
+
+    #[test]
+    fn common_names_are_quoted_when_they_hold_separators() {
+        assert_eq!(parameter_value("Jean Dupont"), "Jean Dupont");
+        assert_eq!(parameter_value("Dupont, Jean"), "\"Dupont, Jean\"");
+        assert_eq!(parameter_value("Jean \"JD\" Dupont"), "Jean JD Dupont");
+        assert_eq!(parameter_value("a;b:c"), "\"a;b:c\"");
+    }
+}
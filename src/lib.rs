@@ -1,7 +1,7 @@
 extern crate reqwest;
 use reqwest::{header, RedirectPolicy};
 pub type Error = failure::Error;
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[macro_use]
 extern crate failure;
@@ -12,52 +12,132 @@ use crypto::digest::Digest;
 use crypto::sha1::Sha1;
 use regex::Regex;
 use select::document::Document;
-use select::predicate::{Attr, Class};
+use select::predicate::{Attr, Class, Name};
 use std::env;
 #[macro_use]
 extern crate serde_derive;
-use chrono::NaiveDate;
-use std::result::Result as StdResult;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate lazy_static;
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
+use schemars::JsonSchema;
+
+pub mod api;
+pub mod auth;
+pub mod backend;
+pub mod booker;
+pub mod clock;
+pub mod club;
+pub mod governor;
+pub mod ics;
+pub mod notifier;
+pub mod opening;
+pub mod planning;
+pub mod runtime;
+pub mod schedule;
+pub mod store;
+pub mod vault;
+pub mod watcher;
+use club::Club;
+use governor::Governed;
 
 const WANAPLAY_END_POINT: &str = "http://fr.wanaplay.com/";
 const WANAPLAY_DATE_FORMAT: &str = "%d/%m/%Y";
+pub const COURT_DURATION_MINUTES: i64 = 40;
 
 pub fn wanaplay_route(route: &str) -> String {
     format!("{}{}", WANAPLAY_END_POINT, route)
 }
 
+/// Every court start time of a day, from 9:00 to 23:00.
+pub fn court_times() -> Vec<NaiveTime> {
+    let mut valid_times = vec![];
+    let mut start_time = NaiveTime::from_hms(9, 0, 0);
+    let end_time = NaiveTime::from_hms(23, 0, 0);
+    while start_time <= end_time {
+        valid_times.push(start_time.clone());
+        start_time =
+            match start_time.overflowing_add_signed(Duration::minutes(COURT_DURATION_MINUTES)) {
+                (val, _) => val,
+            };
+    }
+    valid_times
+}
+
 pub struct WanaplayCredentials {
     pub login: String,
     pub password: WanaplayPassword,
 }
 
-pub struct WanaplayPassword {
-    pub secret_password: String,
+/// The site only ever sees the unsalted SHA-1 digest, so it's enough to keep it.
+pub enum WanaplayPassword {
+    Plain(String),
+    Sha1(String),
 }
 
 impl WanaplayPassword {
     pub fn crypted(&self) -> String {
-        let mut hasher = Sha1::new();
-        hasher.input_str(self.secret_password.as_str());
-        hasher.result_str()
+        match self {
+            WanaplayPassword::Plain(password) => {
+                let mut hasher = Sha1::new();
+                hasher.input_str(password.as_str());
+                hasher.result_str()
+            }
+            WanaplayPassword::Sha1(digest) => digest.clone(),
+        }
+    }
+}
+
+impl Drop for WanaplayPassword {
+    fn drop(&mut self) {
+        match self {
+            WanaplayPassword::Plain(secret) | WanaplayPassword::Sha1(secret) => {
+                vault::zeroize_string(secret)
+            }
+        }
     }
 }
 
+/// Reads `name` from the environment, or from the file `name_FILE` points to,
+/// e.g. a docker secret mounted in `/run/secrets`.
+pub fn credential_var(name: &str) -> Result<String> {
+    if let Ok(value) = env::var(name) {
+        return Ok(value);
+    }
+    match env::var(format!("{}_FILE", name)) {
+        Ok(path) => match std::fs::read_to_string(&path) {
+            Ok(mut content) => {
+                let value = content
+                    .trim_end_matches(|c| c == '\r' || c == '\n')
+                    .to_string();
+                vault::zeroize_string(&mut content);
+                Ok(value)
+            }
+            Err(err) => bail!("unable to read {} from {}: {}", name, path, err),
+        },
+        Err(_) => bail!(
+            "environment variable {} or {}_FILE should be set",
+            name,
+            name
+        ),
+    }
+}
+
+/// Credentials of the vault when `vault_path` is set, of the environment otherwise.
 pub fn get_credentials() -> Result<WanaplayCredentials> {
-    match (env::var("wanaplay_login"), env::var("wanaplay_password")) {
-        (Ok(login), Ok(password)) => Ok(WanaplayCredentials {
-            login,
-            password: WanaplayPassword {
-                secret_password: password,
-            },
-        }),
-        (_, _) => Err(format_err!(
-            "environment variable wanaplay_login and wanaplay_password should be set"
-        )),
+    if let Some(credentials) = vault::credentials_from_env()? {
+        return Ok(credentials);
     }
+    Ok(WanaplayCredentials {
+        login: credential_var("wanaplay_login")?,
+        password: WanaplayPassword::Plain(credential_var("wanaplay_password")?),
+    })
 }
 
-pub fn authenticate(login: String, crypted_password: String) -> Result<reqwest::Client> {
+/// Logs in and returns the headers carrying the session, shared by the blocking and
+/// the async clients.
+pub fn session_headers(login: String, crypted_password: String) -> Result<header::HeaderMap> {
     let authent_client = reqwest::Client::builder()
         .redirect(RedirectPolicy::none())
         .build()
@@ -65,7 +145,7 @@ pub fn authenticate(login: String, crypted_password: String) -> Result<reqwest::
     let authent_response = authent_client
         .post(wanaplay_route("auth/doLogin").as_str())
         .form(&[("login", login), ("sha1mdp", crypted_password)])
-        .send()
+        .governed_send()
         .unwrap();
     let location = authent_response.headers().get(header::LOCATION);
     if location.is_none()
@@ -76,6 +156,15 @@ pub fn authenticate(login: String, crypted_password: String) -> Result<reqwest::
     let session_cookie = authent_response.headers().get(header::SET_COOKIE).unwrap();
     let mut headers = header::HeaderMap::new();
     headers.insert(header::COOKIE, session_cookie.clone());
+    Ok(headers)
+}
+
+pub fn authenticate(login: String, crypted_password: String) -> Result<reqwest::Client> {
+    session_client(session_headers(login, crypted_password)?)
+}
+
+/// Blocking client of the session the headers carry.
+pub fn session_client(headers: header::HeaderMap) -> Result<reqwest::Client> {
     let client = reqwest::Client::builder()
         .default_headers(headers)
         .build()
@@ -84,25 +173,46 @@ pub fn authenticate(login: String, crypted_password: String) -> Result<reqwest::
     client
         .post(wanaplay_route("reservation/planning2").as_str())
         .form(&[("date", "2018-12-24")])
-        .send()
+        .governed_send()
         .unwrap();
     Ok(client)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UserInfos {
     id: String,
     name: String,
 }
 
-pub fn get_user_infos(client: &reqwest::Client, reservation_id: &String) -> Result<UserInfos> {
+/// Fields of the participants of the reservation form, a court takes 4 players.
+const PARTICIPANT_FIELDS: [(&str, &str); 4] = [
+    ("tab_users_id_0", "tab_users_name_0"),
+    ("tab_users_id_1", "tab_users_name_1"),
+    ("tab_users_id_2", "tab_users_name_2"),
+    ("tab_users_id_3", "tab_users_name_3"),
+];
+
+/// Comma separated names of the members booked with the user, `booking_partners`, as
+/// the reservation form lists them.
+pub fn partner_names_from_env() -> Vec<String> {
+    env::var("booking_partners")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn get_reservation_form(client: &reqwest::Client, reservation_id: &str) -> Result<Document> {
     let response = client
         .post(wanaplay_route("reservation/takeReservationShow").as_str())
         .form(&[("idTspl", reservation_id)])
-        .send()?;
+        .governed_send()?;
+    Ok(Document::from_read(response)?)
+}
 
-    let document = Document::from_read(response)?;
-    let infos = match document.find(Attr("id", "users_0")).next() {
+fn read_user_infos(form: &Document, reservation_id: &str) -> Result<UserInfos> {
+    let infos = match form.find(Attr("id", "users_0")).next() {
         Some(infos) => infos.children().next().unwrap(),
         None => bail!(format!(
             "unable to find user infos for reservation {:?}",
@@ -115,78 +225,466 @@ pub fn get_user_infos(client: &reqwest::Client, reservation_id: &String) -> Resu
     })
 }
 
+/// The members named `names`, out of the ones the second participant can be picked from.
+/// Unknown names and players beyond the 4 of a court are left out.
+fn read_partners(form: &Document, names: &[String]) -> Vec<UserInfos> {
+    let members = form
+        .find(Attr("id", "users_1"))
+        .flat_map(|select| select.find(Name("option")))
+        .filter_map(|option| {
+            Some(UserInfos {
+                id: option
+                    .attr("value")
+                    .filter(|id| !id.is_empty())?
+                    .to_string(),
+                name: option.text().trim().to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+    names
+        .iter()
+        .filter_map(|name| {
+            let member = members
+                .iter()
+                .find(|member| member.name.eq_ignore_ascii_case(name));
+            if member.is_none() {
+                eprintln!("{} is not a member of the club", name);
+            }
+            member.cloned()
+        })
+        .take(PARTICIPANT_FIELDS.len() - 1)
+        .collect()
+}
+
+pub fn get_user_infos(client: &reqwest::Client, reservation_id: &str) -> Result<UserInfos> {
+    read_user_infos(
+        &get_reservation_form(client, reservation_id)?,
+        reservation_id,
+    )
+}
+
+/// The user and the partners named `partner_names`, from the reservation form of any
+/// free court.
+pub fn get_participants(
+    client: &reqwest::Client,
+    reservation_id: &str,
+    partner_names: &[String],
+) -> Result<(UserInfos, Vec<UserInfos>)> {
+    let form = get_reservation_form(client, reservation_id)?;
+    Ok((
+        read_user_infos(&form, reservation_id)?,
+        read_partners(&form, partner_names),
+    ))
+}
+
 pub fn do_booking(
     client: &reqwest::Client,
     user_infos: &UserInfos,
     id_booking: &String,
     date: &NaiveDate,
-) {
+) -> Result<()> {
     println!("book");
     println!("{:?}", id_booking);
     client
         .post(wanaplay_route("reservation/takeReservationBase").as_str())
-        .form(&[
-            ("date", date.format("%Y-%m-%d").to_string()),
-            ("idTspl", id_booking.to_string()),
-            ("commit", "Confirmer".to_string()),
-            ("nb_participants", "1".to_string()),
-            ("tab_users_id_0", user_infos.id.clone()),
-            ("tab_users_name_0", user_infos.name.clone()),
-        ])
-        .send()
-        .unwrap();
+        .form(&booking_form(user_infos, &[], id_booking, date))
+        .governed_send()?;
+    Ok(())
+}
+
+/// Fields of the reservation form, the participants are the same for every court.
+pub fn booking_form(
+    user_infos: &UserInfos,
+    partners: &[UserInfos],
+    id_booking: &str,
+    date: &NaiveDate,
+) -> Vec<(&'static str, String)> {
+    let participants = std::iter::once(user_infos).chain(partners);
+    let mut form = vec![
+        ("date", date.format("%Y-%m-%d").to_string()),
+        ("idTspl", id_booking.to_string()),
+        ("commit", "Confirmer".to_string()),
+        ("nb_participants", participants.clone().count().to_string()),
+    ];
+    for ((id_field, name_field), participant) in PARTICIPANT_FIELDS.iter().zip(participants) {
+        form.push((id_field, participant.id.clone()));
+        form.push((name_field, participant.name.clone()));
+    }
+    form
+}
+
+/// Books the slot and returns the resulting booking if wanaplay accepted it,
+/// `None` meaning someone else was faster.
+pub fn book_slot(
+    client: &reqwest::Client,
+    id_booking: &String,
+    date: &NaiveDate,
+) -> Result<Option<Booking>> {
+    let user_infos = get_user_infos(client, id_booking)?;
+    do_booking(client, &user_infos, id_booking, date)?;
+    Ok(fetch_bookings(client)?
+        .into_iter()
+        .find(|booking| booking.id == *id_booking))
 }
 
 pub fn get_logged_client() -> Result<reqwest::Client> {
-    let credentials = WanaplayCredentials {
-        login: env::var("wanaplay_login").unwrap(),
-        password: WanaplayPassword {
-            secret_password: env::var("wanaplay_password").unwrap(),
-        },
-    };
+    let credentials = get_credentials()?;
     authenticate(credentials.login, credentials.password.crypted())
 }
 
-pub fn ser_from_naive_date<S>(date: &NaiveDate, serializer: S) -> StdResult<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    let s = format!("{}", date.format(WANAPLAY_DATE_FORMAT));
-    serializer.serialize_str(&s)
+/// (De)serializes court times as `HH:MM`, also accepting `HH:MM:SS`.
+pub mod serde_court_time {
+    use chrono::NaiveTime;
+    use schemars::gen::SchemaGenerator;
+    use schemars::schema::Schema;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// The court times, `HH:MM`.
+    pub fn schema(_: &mut SchemaGenerator) -> Schema {
+        let times = crate::court_times()
+            .iter()
+            .map(|time| time.format("%H:%M").to_string())
+            .collect::<Vec<_>>();
+        serde_json::from_value(json!({"type": "string", "enum": times})).unwrap()
+    }
+
+    pub fn serialize<S>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&time.format("%H:%M").to_string())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<NaiveTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let time = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(&time, "%H:%M")
+            .or_else(|_| NaiveTime::parse_from_str(&time, "%H:%M:%S"))
+            .map_err(serde::de::Error::custom)
+    }
 }
 
-#[derive(Debug, Serialize, Clone)]
+/// (De)serializes lists of court times like `serde_court_time`.
+pub mod serde_court_times {
+    use chrono::NaiveTime;
+    use schemars::gen::SchemaGenerator;
+    use schemars::schema::Schema;
+    use serde::ser::SerializeSeq;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn schema(generator: &mut SchemaGenerator) -> Schema {
+        let time = super::serde_court_time::schema(generator);
+        serde_json::from_value(json!({"type": "array", "items": time})).unwrap()
+    }
+
+    pub fn serialize<S>(times: &[NaiveTime], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(times.len()))?;
+        for time in times {
+            seq.serialize_element(&time.format("%H:%M").to_string())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<NaiveTime>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|time| {
+                NaiveTime::parse_from_str(time, "%H:%M")
+                    .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M:%S"))
+                    .map_err(serde::de::Error::custom)
+            })
+            .collect()
+    }
+}
+
+/// (De)serializes week days by their full english name, e.g. `Thursday`.
+pub mod serde_week_day {
+    use chrono::Weekday;
+    use schemars::gen::SchemaGenerator;
+    use schemars::schema::Schema;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn schema(_: &mut SchemaGenerator) -> Schema {
+        let names = [
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ]
+        .iter()
+        .map(name)
+        .collect::<Vec<_>>();
+        serde_json::from_value(json!({"type": "string", "enum": names})).unwrap()
+    }
+
+    pub fn name(week_day: &Weekday) -> &'static str {
+        match week_day {
+            Weekday::Mon => "Monday",
+            Weekday::Tue => "Tuesday",
+            Weekday::Wed => "Wednesday",
+            Weekday::Thu => "Thursday",
+            Weekday::Fri => "Friday",
+            Weekday::Sat => "Saturday",
+            Weekday::Sun => "Sunday",
+        }
+    }
+
+    pub fn serialize<S>(week_day: &Weekday, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(name(week_day))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Weekday, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let week_day = String::deserialize(deserializer)?;
+        week_day
+            .parse::<Weekday>()
+            .map_err(|_| serde::de::Error::custom(format!("{} is not a valid week day", week_day)))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Booking {
     pub id: String,
-    #[serde(serialize_with = "ser_from_naive_date")]
-    pub date: NaiveDate,
-    pub court_time: String,
+    /// in the club time zone, serialized as RFC 3339
+    pub start: DateTime<FixedOffset>,
     pub court_number: u8,
 }
 
+impl Booking {
+    /// Start on the club wall clock.
+    pub fn local_start(&self) -> NaiveDateTime {
+        self.start.naive_local()
+    }
+
+    pub fn date(&self) -> NaiveDate {
+        self.local_start().date()
+    }
+
+    pub fn court_time(&self) -> String {
+        self.start.format("%H:%M").to_string()
+    }
+}
+
 pub fn get_bookings() -> Vec<Booking> {
     let client = get_logged_client().unwrap();
+    fetch_bookings(&client).unwrap()
+}
+
+pub fn fetch_bookings(client: &reqwest::Client) -> Result<Vec<Booking>> {
     let response = client
-        .get(wanaplay_route("plannings/espacesportifpontoise").as_str())
-        .send()
-        .unwrap();
-    let document = Document::from_read(response).unwrap();
-    document
+        .get(wanaplay_route(Club::from_env().planning_route().as_str()).as_str())
+        .governed_send()?;
+    let document = Document::from_read(response)?;
+    Ok(document
         .find(Class("lienMyRes"))
         .map(|resa| {
             let re = Regex::new(r"(.+)\u{a0}(.+)\u{a0}Court (\d)").unwrap();
             let resa_line = resa.children().next().unwrap().text();
             let matches = re.captures(resa_line.as_str()).unwrap();
+            let date =
+                NaiveDate::parse_from_str(matches.get(1).unwrap().as_str(), WANAPLAY_DATE_FORMAT)
+                    .unwrap();
+            let court_time =
+                NaiveTime::parse_from_str(matches.get(2).unwrap().as_str(), "%H:%M").unwrap();
             Booking {
                 id: resa.attr("href").unwrap().rsplit("/").collect::<Vec<_>>()[0].into(),
-                date: NaiveDate::parse_from_str(
-                    matches.get(1).unwrap().as_str(),
-                    WANAPLAY_DATE_FORMAT,
-                )
-                .unwrap(),
-                court_time: matches.get(2).unwrap().as_str().into(),
+                start: club::club_time(date.and_time(court_time)),
                 court_number: matches.get(3).unwrap().as_str().parse().unwrap(),
             }
         })
-        .collect::<Vec<_>>()
+        .collect::<Vec<_>>())
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Participant {
+    pub id: String,
+    pub name: String,
+}
+
+pub fn get_booking_participants(
+    client: &reqwest::Client,
+    booking_id: &str,
+) -> Result<Vec<Participant>> {
+    let response = client
+        .get(
+            wanaplay_route(
+                format!("reservation/modifyReservationBase?idTspl={}", booking_id).as_str(),
+            )
+            .as_str(),
+        )
+        .governed_send()?;
+    let document = Document::from_read(response)?;
+    let inputs = document.find(Name("input")).collect::<Vec<_>>();
+    let participants = inputs
+        .iter()
+        .filter_map(|input| {
+            let name = input.attr("name")?;
+            if !name.starts_with("tab_users_id_") {
+                return None;
+            }
+            let index = name.trim_start_matches("tab_users_id_");
+            let user_name = inputs
+                .iter()
+                .find(|other| {
+                    other.attr("name") == Some(format!("tab_users_name_{}", index).as_str())
+                })
+                .and_then(|other| other.attr("value"))
+                .unwrap_or_default();
+            Some(Participant {
+                id: input.attr("value").unwrap_or_default().to_string(),
+                name: user_name.to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+    // the one who booked is always listed, the page of an expired session lists no one
+    if participants.is_empty() {
+        bail!("no participant found on booking {}", booking_id);
+    }
+    Ok(participants)
+}
+
+pub fn cancel_booking(client: &reqwest::Client, id: &str) -> Result<()> {
+    client
+        .get(
+            wanaplay_route(
+                format!(
+                    "reservation/modifyReservationBase?idTspl={}&user_action=delete",
+                    id
+                )
+                .as_str(),
+            )
+            .as_str(),
+        )
+        .governed_send()?;
+    if fetch_bookings(client)?
+        .iter()
+        .any(|booking| booking.id == id)
+    {
+        bail!("booking {} was not cancelled", id);
+    }
+    Ok(())
+}
+
+fn fetch_planning(client: &reqwest::Client, date: &NaiveDate) -> Result<Document> {
+    let response = client
+        .post(wanaplay_route("reservation/planning2").as_str())
+        .form(&[("date", date.format("%Y-%m-%d").to_string())])
+        .governed_send()?;
+    Ok(Document::from_read(response)?)
+}
+
+fn slot_time(node: &select::node::Node) -> Option<String> {
+    Some(node.children().next()?.children().next()?.text())
+}
+
+pub fn get_time_slots(client: &reqwest::Client, date: &NaiveDate) -> Result<Vec<NaiveTime>> {
+    Ok(planning::free_times(&planning::fetch_cells(client, *date)?))
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[schemars(rename = "Court")]
+pub struct CourtWithId {
+    pub court_number: u8,
+    /// slot_id to book the court
+    pub booking_id: String,
+}
+
+pub fn get_court_number(client: &reqwest::Client, id: &str) -> Result<u8> {
+    let book_response = client
+        .get(
+            wanaplay_route(("reservation/takeReservationShow?idTspl=".to_string() + id).as_ref())
+                .as_str(),
+        )
+        .governed_send()?;
+    let book_doc = Document::from_read(book_response)?;
+    let court = book_doc
+        .find(Attr("action", "/reservation/takeReservationConfirm"))
+        .next()
+        .and_then(|resa_form| {
+            resa_form
+                .find(Name("p"))
+                .find(|node| node.text().contains("Terrain"))
+        })
+        .and_then(|terrain_node| terrain_node.children().nth(2))
+        .map(|court| court.text());
+    let re = Regex::new(r"Court (\d)").unwrap();
+    match court.as_ref().and_then(|court| re.captures(court)) {
+        Some(matches) => Ok(matches.get(1).unwrap().as_str().parse::<u8>()?),
+        None => bail!("unable to find court of {}", id),
+    }
+}
+
+pub fn get_available_courts(
+    client: &reqwest::Client,
+    datetime: &NaiveDateTime,
+) -> Result<Vec<CourtWithId>> {
+    let cells = planning::fetch_cells(client, datetime.date())?;
+    get_courts(client, planning::free_slot_ids(&cells, datetime.time()))
+}
+
+/// Court numbers of the slots, wanaplay only shows them on the booking page.
+pub fn get_courts(client: &reqwest::Client, slot_ids: Vec<String>) -> Result<Vec<CourtWithId>> {
+    slot_ids
+        .into_iter()
+        .map(|id| {
+            Ok(CourtWithId {
+                court_number: get_court_number(client, &id)?,
+                booking_id: id,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(id: &str, name: &str) -> UserInfos {
+        UserInfos {
+            id: id.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn partners_are_sent_after_the_user() {
+        let partners = vec![member("56", "Paul Martin"), member("34", "Marie Curie")];
+        let form = booking_form(
+            &member("12", "Jean Dupont"),
+            &partners,
+            "1",
+            &NaiveDate::from_ymd(2024, 3, 6),
+        );
+        assert_eq!(
+            form,
+            vec![
+                ("date", "2024-03-06".to_string()),
+                ("idTspl", "1".to_string()),
+                ("commit", "Confirmer".to_string()),
+                ("nb_participants", "3".to_string()),
+                ("tab_users_id_0", "12".to_string()),
+                ("tab_users_name_0", "Jean Dupont".to_string()),
+                ("tab_users_id_1", "56".to_string()),
+                ("tab_users_name_1", "Paul Martin".to_string()),
+                ("tab_users_id_2", "34".to_string()),
+                ("tab_users_name_2", "Marie Curie".to_string()),
+            ]
+        );
+    }
 }
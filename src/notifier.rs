@@ -0,0 +1,431 @@
+use crate::auth::ActionSigner;
+use crate::{Booking, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use lettre::smtp::authentication::Credentials;
+use lettre::{SmtpClient, Transport};
+use lettre_email::Email;
+use schemars::JsonSchema;
+use std::env;
+
+const TELEGRAM_API_URL: &str = "https://api.telegram.org";
+/// Action the cancellation links are signed for.
+pub const CANCEL_ACTION: &str = "cancel";
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Reminder,
+    BookingSucceeded,
+    BookingFailed,
+    BotDeployed,
+    BotDeployFailed,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<BookingOutcome>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<EventAction>,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, message: String) -> Self {
+        Event {
+            kind,
+            message,
+            outcome: None,
+            actions: vec![],
+        }
+    }
+}
+
+/// Something the recipient can do about the event, e.g. cancelling a booking on the proxy.
+#[derive(Debug, Serialize, Clone)]
+pub struct EventAction {
+    pub label: String,
+    pub method: String,
+    pub url: String,
+    /// callback understood by the telegram chatbot, sent instead of the url when set
+    #[serde(skip_serializing)]
+    pub telegram_callback: Option<String>,
+}
+
+impl EventAction {
+    /// Link to the page of the proxy confirming the cancellation, signed so that it
+    /// opens from an email or a button without an API key, until the booking starts.
+    pub fn cancel_booking(
+        proxy_url: &str,
+        signer: &ActionSigner,
+        booking: &Booking,
+        label: String,
+    ) -> Self {
+        let expires = booking.start.timestamp();
+        EventAction {
+            label,
+            method: "GET".to_string(),
+            url: format!(
+                "{}/bookings/{}/cancel?expires={}&signature={}",
+                proxy_url.trim_end_matches('/'),
+                booking.id,
+                expires,
+                signer.sign(CANCEL_ACTION, &booking.id, expires)
+            ),
+            telegram_callback: Some(json!({"a": "cancel", "d": booking.id}).to_string()),
+        }
+    }
+}
+
+/// What happened when the booker tried to book a slot at opening time. Court starts
+/// are club times, serialized as RFC 3339.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BookingOutcome {
+    Booked {
+        start: DateTime<FixedOffset>,
+        court_number: u8,
+    },
+    FellBack {
+        wanted_start: DateTime<FixedOffset>,
+        start: DateTime<FixedOffset>,
+        court_number: u8,
+    },
+    NothingFree {
+        starts: Vec<DateTime<FixedOffset>>,
+    },
+    LoginFailed {
+        date: NaiveDate,
+        reason: String,
+    },
+    NeverOpened {
+        date: NaiveDate,
+    },
+    /// booking requests were sent but the bookings couldn't be read back, a court may
+    /// be booked
+    Unconfirmed {
+        date: NaiveDate,
+        reason: String,
+    },
+}
+
+impl BookingOutcome {
+    pub fn is_booked(&self) -> bool {
+        match self {
+            BookingOutcome::Booked { .. } | BookingOutcome::FellBack { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            BookingOutcome::Booked {
+                start,
+                court_number,
+            } => format!(
+                "booked court {} on {}",
+                court_number,
+                start.format("%a %d/%m at %H:%M")
+            ),
+            BookingOutcome::FellBack {
+                wanted_start,
+                start,
+                court_number,
+            } => format!(
+                "{} was taken, fell back to court {} on {}",
+                wanted_start.format("%H:%M"),
+                court_number,
+                start.format("%a %d/%m at %H:%M")
+            ),
+            BookingOutcome::NothingFree { starts } => format!(
+                "nothing free on {} at {}",
+                starts
+                    .first()
+                    .map(|start| start.format("%a %d/%m").to_string())
+                    .unwrap_or_default(),
+                starts
+                    .iter()
+                    .map(|start| start.format("%H:%M").to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            BookingOutcome::LoginFailed { date, reason } => format!(
+                "unable to login to book {}: {}",
+                date.format("%a %d/%m"),
+                reason
+            ),
+            BookingOutcome::NeverOpened { date } => {
+                format!("planning of {} never opened", date.format("%a %d/%m"))
+            }
+            BookingOutcome::Unconfirmed { date, reason } => format!(
+                "unable to confirm the booking of {}, check the bookings: {}",
+                date.format("%a %d/%m"),
+                reason
+            ),
+        }
+    }
+}
+
+impl From<BookingOutcome> for Event {
+    fn from(outcome: BookingOutcome) -> Self {
+        Event {
+            kind: match outcome.is_booked() {
+                true => EventKind::BookingSucceeded,
+                false => EventKind::BookingFailed,
+            },
+            message: outcome.message(),
+            outcome: Some(outcome),
+            actions: vec![],
+        }
+    }
+}
+
+pub trait Notifier {
+    fn notify(&self, event: &Event) -> Result<()>;
+}
+
+pub struct StdoutNotifier;
+
+impl Notifier for StdoutNotifier {
+    fn notify(&self, event: &Event) -> Result<()> {
+        println!("[{:?}] {}", event.kind, event.message);
+        Ok(())
+    }
+}
+
+pub struct TelegramNotifier {
+    client: reqwest::Client,
+    api_url: String,
+    bot_token: String,
+    chat_id: String,
+}
+
+impl TelegramNotifier {
+    pub fn from_env() -> Result<Self> {
+        Ok(TelegramNotifier {
+            client: reqwest::Client::new(),
+            api_url: env::var("telegram_api_url").unwrap_or_else(|_| TELEGRAM_API_URL.to_string()),
+            bot_token: env::var("bot_token")?,
+            chat_id: env::var("chat_id")?,
+        })
+    }
+}
+
+impl Notifier for TelegramNotifier {
+    fn notify(&self, event: &Event) -> Result<()> {
+        let mut params = vec![
+            ("chat_id", self.chat_id.clone()),
+            ("text", event.message.clone()),
+        ];
+        if !event.actions.is_empty() {
+            let buttons = event
+                .actions
+                .iter()
+                .map(|action| match &action.telegram_callback {
+                    Some(callback) => json!({"text": action.label, "callback_data": callback}),
+                    None => json!({"text": action.label, "url": action.url}),
+                })
+                .map(|button| vec![button])
+                .collect::<Vec<_>>();
+            params.push((
+                "reply_markup",
+                json!({ "inline_keyboard": buttons }).to_string(),
+            ));
+        }
+        self.client
+            .post(format!("{}/bot{}/sendMessage", self.api_url, self.bot_token).as_str())
+            .form(&params)
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn from_env() -> Result<Self> {
+        Ok(WebhookNotifier {
+            client: reqwest::Client::new(),
+            url: env::var("webhook_url")?,
+        })
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, event: &Event) -> Result<()> {
+        self.client
+            .post(self.url.as_str())
+            .json(event)
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+pub struct EmailNotifier {
+    server: String,
+    credentials: Option<(String, String)>,
+    from: String,
+    to: String,
+}
+
+impl EmailNotifier {
+    pub fn from_env() -> Result<Self> {
+        Ok(EmailNotifier {
+            server: env::var("smtp_server")?,
+            credentials: match (env::var("smtp_username"), env::var("smtp_password")) {
+                (Ok(username), Ok(password)) => Some((username, password)),
+                (_, _) => None,
+            },
+            from: env::var("email_from")?,
+            to: env::var("email_to")?,
+        })
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn notify(&self, event: &Event) -> Result<()> {
+        let email = Email::builder()
+            .from(self.from.as_str())
+            .to(self.to.as_str())
+            .subject(format!("[wanaplay] {:?}", event.kind))
+            .text(
+                std::iter::once(event.message.clone())
+                    .chain(event.actions.iter().map(|action| {
+                        format!("{}: {} {}", action.label, action.method, action.url)
+                    }))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )
+            .build()?;
+        let mut client = SmtpClient::new_simple(self.server.as_str())?;
+        if let Some((username, password)) = &self.credentials {
+            client = client.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        client.transport().send(email.into())?;
+        Ok(())
+    }
+}
+
+/// Fans an event out to every configured notifier, failing if any of them failed.
+pub struct Notifiers {
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+
+impl Notifier for Notifiers {
+    fn notify(&self, event: &Event) -> Result<()> {
+        let errors = self
+            .notifiers
+            .iter()
+            .filter_map(|notifier| notifier.notify(event).err())
+            .map(|err| err.to_string())
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            bail!("unable to notify: {}", errors.join(", "));
+        }
+        Ok(())
+    }
+}
+
+/// `notifiers` is a comma separated list among telegram, webhook, email and stdout.
+/// When unset, telegram is used if `bot_token` is set, stdout otherwise.
+pub fn notifier_from_env() -> Result<Notifiers> {
+    let names = match env::var("notifiers") {
+        Ok(names) => names,
+        Err(_) if env::var("bot_token").is_ok() => "telegram".to_string(),
+        Err(_) => "stdout".to_string(),
+    };
+    let mut notifiers: Vec<Box<dyn Notifier>> = vec![];
+    for name in names.split(',').map(str::trim) {
+        notifiers.push(match name {
+            "telegram" => Box::new(TelegramNotifier::from_env()?),
+            "webhook" => Box::new(WebhookNotifier::from_env()?),
+            "email" => Box::new(EmailNotifier::from_env()?),
+            "stdout" => Box::new(StdoutNotifier),
+            _ => bail!("{} is not a valid notifier", name),
+        });
+    }
+    Ok(Notifiers { notifiers })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct RecordingNotifier {
+        messages: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, event: &Event) -> Result<()> {
+            self.messages.borrow_mut().push(event.message.clone());
+            Ok(())
+        }
+    }
+
+    struct FailingNotifier;
+
+    impl Notifier for FailingNotifier {
+        fn notify(&self, _: &Event) -> Result<()> {
+            bail!("unreachable smtp server")
+        }
+    }
+
+    #[test]
+    fn outcomes_are_tagged_with_their_status() {
+        let start = DateTime::parse_from_rfc3339("2026-11-05T19:40:00+01:00").unwrap();
+        let booked = BookingOutcome::Booked {
+            start,
+            court_number: 3,
+        };
+        let value = json!({
+            "status": "booked",
+            "start": "2026-11-05T19:40:00+01:00",
+            "court_number": 3,
+        });
+        assert_eq!(serde_json::to_value(&booked).unwrap(), value);
+        let read = serde_json::from_value::<BookingOutcome>(value).unwrap();
+        assert_eq!(read.message(), booked.message());
+        let never_opened = BookingOutcome::NeverOpened {
+            date: NaiveDate::from_ymd(2026, 11, 5),
+        };
+        assert_eq!(
+            serde_json::to_value(&never_opened).unwrap(),
+            json!({"status": "never_opened", "date": "2026-11-05"})
+        );
+
+        let event = serde_json::to_value(Event::from(booked)).unwrap();
+        assert_eq!(event["kind"], "booking_succeeded");
+        assert_eq!(event["outcome"]["status"], "booked");
+        assert!(event.get("actions").is_none());
+        let event = serde_json::to_value(Event::from(never_opened)).unwrap();
+        assert_eq!(event["kind"], "booking_failed");
+    }
+
+    #[test]
+    fn every_notifier_is_notified_even_after_a_failure() {
+        let (first, last) = (RecordingNotifier::default(), RecordingNotifier::default());
+        let (first_messages, last_messages) = (first.messages.clone(), last.messages.clone());
+        let notifiers = Notifiers {
+            notifiers: vec![Box::new(first), Box::new(FailingNotifier), Box::new(last)],
+        };
+        let event = Event::new(EventKind::Reminder, "court 3 at 19:40".to_string());
+        let err = notifiers.notify(&event).unwrap_err();
+        assert_eq!(err.to_string(), "unable to notify: unreachable smtp server");
+        assert_eq!(*first_messages.borrow(), ["court 3 at 19:40"]);
+        assert_eq!(*last_messages.borrow(), ["court 3 at 19:40"]);
+
+        let notifiers = Notifiers {
+            notifiers: vec![Box::new(RecordingNotifier::default())],
+        };
+        assert!(notifiers.notify(&event).is_ok());
+    }
+}
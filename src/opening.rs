@@ -0,0 +1,448 @@
+use crate::booker::{
+    attempt_booking, court_starts, planning_opening, BookerParameters, BOOKING_HORIZON_DAYS,
+    OPENING_TIMEOUT_MINUTES, PLANNING_NOT_OPEN,
+};
+use crate::clock::{estimate_clock, DEFAULT_CLOCK_SAMPLES};
+use crate::club::club_time;
+use crate::governor::{governor, is_overloaded, Permit};
+use crate::notifier::BookingOutcome;
+use crate::planning::{fetch_cells, free_slot_ids, parse_planning, PlanningCell};
+use crate::runtime::Session;
+use crate::{
+    booking_form, cancel_booking, fetch_bookings, get_participants, partner_names_from_env,
+    wanaplay_route, Booking, Result, UserInfos,
+};
+use chrono::prelude::*;
+use chrono::Duration;
+use futures::future::{self, Future};
+use reqwest::r#async::{Client, Response};
+use select::document::Document;
+use std::env;
+use std::thread;
+use std::time;
+use tokio::runtime::current_thread::Runtime;
+
+const DEFAULT_CONCURRENT_ATTEMPTS: usize = 2;
+const OPENING_POLL_MILLISECONDS: u64 = 500;
+/// How long the bookings are read again after a batch before giving up on knowing.
+const CHECK_TIMEOUT_MINUTES: i64 = 10;
+const CHECK_RETRY_SECONDS: u64 = 2;
+
+/// How many courts are booked at once, `opening_attempts`, 2 by default, within the
+/// concurrent requests the governor allows. Several courts can be won on purpose, even
+/// one at the wanted time and one at a fallback time, the extra ones are cancelled
+/// right after. 1 books one court at a time.
+fn concurrent_attempts() -> usize {
+    env::var("opening_attempts")
+        .ok()
+        .and_then(|attempts| attempts.parse().ok())
+        .filter(|attempts| *attempts > 0)
+        .unwrap_or(DEFAULT_CONCURRENT_ATTEMPTS)
+        .min(governor().max_batch())
+}
+
+/// Reads the user and the partners of the reservation form on a free court of a date
+/// already open, so that booking at opening only takes the final request.
+pub fn prefetch_participants(
+    client: &reqwest::Client,
+    target_date: NaiveDate,
+    partner_names: &[String],
+) -> Result<(UserInfos, Vec<UserInfos>)> {
+    let today = Local::now().date().naive_local();
+    for days in 1..=BOOKING_HORIZON_DAYS {
+        let date = target_date - Duration::days(days);
+        if date < today {
+            break;
+        }
+        if let Some(id) = fetch_cells(client, date)?
+            .into_iter()
+            .find_map(|cell| cell.slot_id)
+        {
+            return get_participants(client, &id, partner_names);
+        }
+    }
+    bail!(
+        "no free court before {} to read the reservation form from",
+        target_date
+    )
+}
+
+/// What doesn't depend on the opening, resolved in the minutes before it.
+pub struct Preparation {
+    pub user_infos: Option<UserInfos>,
+    /// members booked with the user
+    pub partners: Vec<UserInfos>,
+    /// server clock minus the local clock
+    pub clock_offset: Duration,
+}
+
+/// Resolves the ids of the user and of the partners, and the server clock offset. Their
+/// failures are only logged, the booking can do without them.
+pub fn prepare(
+    client: &reqwest::Client,
+    target_date: NaiveDate,
+    partner_names: &[String],
+) -> Preparation {
+    let (user_infos, partners) = match prefetch_participants(client, target_date, partner_names) {
+        Ok((user_infos, partners)) => (Some(user_infos), partners),
+        Err(err) => {
+            eprintln!("unable to prepare the reservation form: {}", err);
+            (None, vec![])
+        }
+    };
+    let clock_offset = match estimate_clock(client, DEFAULT_CLOCK_SAMPLES) {
+        Ok(estimate) => {
+            println!(
+                "server clock offset {}ms ± {}ms",
+                estimate.offset_ms, estimate.uncertainty_ms
+            );
+            estimate.offset()
+        }
+        Err(err) => {
+            eprintln!("unable to read the server clock: {}", err);
+            Duration::zero()
+        }
+    };
+    println!("prepared {:?} with {:?}", user_infos, partners);
+    Preparation {
+        user_infos,
+        partners,
+        clock_offset,
+    }
+}
+
+/// Sleeps until the server clock reaches the opening of the planning of `target_date`.
+fn wait_for_opening(target_date: NaiveDate, clock_offset: Duration) {
+    let opening = planning_opening(target_date).with_timezone(&Utc) - clock_offset;
+    if let Ok(wait) = (opening - Utc::now()).to_std() {
+        println!("first poll in {:?}", wait);
+        thread::sleep(wait);
+    }
+}
+
+/// Sends the request the governor allowed with the permit.
+fn governed_send(
+    client: &Client,
+    permit: Permit,
+    url: &str,
+    form: &[(&str, String)],
+) -> impl Future<Item = Response, Error = reqwest::Error> {
+    client.post(url).form(form).send().then(move |response| {
+        permit.finish(match &response {
+            Ok(response) => is_overloaded(response.status()),
+            Err(_) => true,
+        });
+        response
+    })
+}
+
+/// The planning of `date`, `None` while it isn't open.
+fn open_planning(
+    client: &Client,
+    date: NaiveDate,
+) -> impl Future<Item = Option<Vec<PlanningCell>>, Error = reqwest::Error> {
+    let url = wanaplay_route("reservation/planning2");
+    let form = [("date", date.format("%Y-%m-%d").to_string())];
+    let permit = governor().acquire_urgent_one(&url);
+    governed_send(client, permit, &url, &form)
+        .and_then(|mut response| response.text())
+        .map(|page| match page.contains(PLANNING_NOT_OPEN) {
+            true => None,
+            false => Some(parse_planning(&Document::from(page.as_str()))),
+        })
+}
+
+fn post_booking(
+    client: &Client,
+    permit: Permit,
+    form: Vec<(&'static str, String)>,
+) -> impl Future<Item = (), Error = reqwest::Error> {
+    let url = wanaplay_route("reservation/takeReservationBase");
+    governed_send(client, permit, &url, &form).map(|_| ())
+}
+
+/// Free courts in the order they're wanted: the court time then the fallbacks, the
+/// second court first when the four are free.
+fn candidates(cells: &[PlanningCell], court_times: &[NaiveTime]) -> Vec<(NaiveTime, String)> {
+    court_times
+        .iter()
+        .flat_map(|time| {
+            let time = *time;
+            let mut ids = free_slot_ids(cells, time);
+            if ids.len() == 4 {
+                ids.swap(0, 1);
+            }
+            ids.into_iter().map(move |id| (time, id))
+        })
+        .collect()
+}
+
+/// The outcome of a batch of candidates and the extra courts to cancel, the best court
+/// won is kept, `None` when every court of the batch was taken.
+fn settle<'a>(
+    parameters: &BookerParameters,
+    target_date: NaiveDate,
+    batch: &[(NaiveTime, String)],
+    bookings: &'a [Booking],
+) -> Option<(BookingOutcome, Vec<&'a Booking>)> {
+    let mut won = batch.iter().filter_map(|(court_time, id)| {
+        Some((
+            *court_time,
+            bookings.iter().find(|booking| booking.id == *id)?,
+        ))
+    });
+    let (court_time, booking) = won.next()?;
+    let outcome = match court_time == parameters.court_time {
+        true => BookingOutcome::Booked {
+            start: booking.start,
+            court_number: booking.court_number,
+        },
+        false => BookingOutcome::FellBack {
+            wanted_start: club_time(target_date.and_time(parameters.court_time)),
+            start: booking.start,
+            court_number: booking.court_number,
+        },
+    };
+    Some((outcome, won.map(|(_, extra)| extra).collect()))
+}
+
+/// Reads the bookings until it works, the court may be booked and its extras not
+/// cancelled yet.
+fn check_bookings(session: &Session) -> Result<Vec<Booking>> {
+    let deadline = Local::now() + Duration::minutes(CHECK_TIMEOUT_MINUTES);
+    loop {
+        match session.client().and_then(|client| fetch_bookings(&client)) {
+            Ok(bookings) => return Ok(bookings),
+            Err(err) if Local::now() > deadline => return Err(err),
+            Err(err) => eprintln!("unable to check the bookings: {}", err),
+        }
+        thread::sleep(time::Duration::from_secs(CHECK_RETRY_SECONDS));
+    }
+}
+
+/// Books when the planning of `target_date` opens, firing the booking requests of the
+/// best candidates concurrently then keeping the best court won. The session is renewed
+/// and the reservation form prepared beforehand, the first poll is at the opening by the
+/// server clock. Books one court at a time when the async client can't start.
+pub fn book_at_opening(
+    session: &Session,
+    parameters: &BookerParameters,
+    target_date: NaiveDate,
+) -> BookingOutcome {
+    let client = match session.renew() {
+        Ok(client) => client,
+        Err(err) => {
+            return BookingOutcome::LoginFailed {
+                date: target_date,
+                reason: err.to_string(),
+            }
+        }
+    };
+    let started = session
+        .async_client()
+        .and_then(|async_client| Ok((async_client, Runtime::new()?)));
+    let (async_client, mut runtime) = match started {
+        Ok(started) => started,
+        Err(err) => {
+            eprintln!("unable to start the async client: {}", err);
+            return attempt_booking(&client, parameters, target_date);
+        }
+    };
+    let partner_names = partner_names_from_env();
+    let Preparation {
+        mut user_infos,
+        mut partners,
+        clock_offset,
+    } = prepare(&client, target_date, &partner_names);
+    wait_for_opening(target_date, clock_offset);
+    let deadline = Local::now() + Duration::minutes(OPENING_TIMEOUT_MINUTES);
+    let cells = loop {
+        println!("watch_openning {:?} at {:?}", target_date, Local::now());
+        match runtime.block_on(open_planning(&async_client, target_date)) {
+            Ok(Some(cells)) => break cells,
+            Ok(None) => (),
+            Err(err) => eprintln!("unable to watch opening: {}", err),
+        }
+        if Local::now() > deadline {
+            return BookingOutcome::NeverOpened { date: target_date };
+        }
+        thread::sleep(time::Duration::from_millis(OPENING_POLL_MILLISECONDS));
+    };
+    let court_times = std::iter::once(parameters.court_time)
+        .chain(parameters.fallback_times.iter().cloned())
+        .collect::<Vec<_>>();
+    for batch in candidates(&cells, &court_times).chunks(concurrent_attempts()) {
+        if user_infos.is_none() {
+            match get_participants(&client, &batch[0].1, &partner_names) {
+                Ok((user, partners_read)) => {
+                    user_infos = Some(user);
+                    partners = partners_read;
+                }
+                Err(err) => eprintln!("unable to read the reservation form: {}", err),
+            }
+        }
+        let user_infos = match &user_infos {
+            Some(user_infos) => user_infos,
+            None => continue,
+        };
+        println!("book {:?}", batch);
+        let permits = governor().acquire_urgent(
+            &wanaplay_route("reservation/takeReservationBase"),
+            batch.len(),
+        );
+        let attempts = batch
+            .iter()
+            .zip(permits)
+            .map(|((_, id), permit)| {
+                let form = booking_form(user_infos, &partners, id, &target_date);
+                post_booking(&async_client, permit, form).then(Ok::<_, ()>)
+            })
+            .collect::<Vec<_>>();
+        let posted = runtime
+            .block_on(future::join_all(attempts))
+            .unwrap_or_default();
+        for ((_, id), posted) in batch.iter().zip(posted) {
+            if let Err(err) = posted {
+                eprintln!("unable to book {:?}: {}", id, err);
+            }
+        }
+        // the bookings tell which requests won, without them the next batch could
+        // book a second court
+        let bookings = match check_bookings(session) {
+            Ok(bookings) => bookings,
+            Err(err) => {
+                return BookingOutcome::Unconfirmed {
+                    date: target_date,
+                    reason: err.to_string(),
+                }
+            }
+        };
+        if let Some((outcome, extras)) = settle(parameters, target_date, batch, &bookings) {
+            for extra in extras {
+                match cancel_booking(&client, &extra.id) {
+                    Ok(()) => println!("cancelled extra court {}", extra.court_number),
+                    Err(err) => eprintln!("unable to cancel extra booking {:?}: {}", extra.id, err),
+                }
+            }
+            return outcome;
+        }
+        println!("{:?} were taken", batch);
+    }
+    BookingOutcome::NothingFree {
+        starts: court_starts(target_date, &court_times),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::booker::{Schedule, WatcherKind};
+
+    fn time(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    fn cell(at: &str, slot_id: Option<&str>) -> PlanningCell {
+        PlanningCell {
+            time: time(at),
+            free: slot_id.is_some(),
+            slot_id: slot_id.map(str::to_string),
+        }
+    }
+
+    fn parameters() -> BookerParameters {
+        BookerParameters {
+            kind: WatcherKind::Opening,
+            schedule: Schedule::Once {
+                date: NaiveDate::from_ymd(2024, 3, 20),
+            },
+            court_time: time("19:00"),
+            fallback_times: vec![time("19:40")],
+            skip: vec![],
+        }
+    }
+
+    fn booking(id: &str, at: &str, court_number: u8) -> Booking {
+        Booking {
+            id: id.to_string(),
+            start: club_time(NaiveDate::from_ymd(2024, 3, 20).and_time(time(at))),
+            court_number,
+        }
+    }
+
+    fn ids(candidates: &[(NaiveTime, String)]) -> Vec<&str> {
+        candidates.iter().map(|(_, id)| id.as_str()).collect()
+    }
+
+    fn ids_of<'a>(bookings: &[&'a Booking]) -> Vec<&'a str> {
+        bookings.iter().map(|booking| booking.id.as_str()).collect()
+    }
+
+    #[test]
+    fn candidates_follow_the_court_times_then_the_page() {
+        let cells = vec![
+            cell("19:00", Some("a1")),
+            cell("19:00", None),
+            cell("19:00", Some("a3")),
+            cell("19:40", Some("b1")),
+            cell("20:20", Some("c1")),
+        ];
+        let candidates = candidates(&cells, &[time("19:40"), time("19:00")]);
+        assert_eq!(ids(&candidates), vec!["b1", "a1", "a3"]);
+        assert_eq!(candidates[0].0, time("19:40"));
+    }
+
+    #[test]
+    fn the_second_court_is_tried_first_when_the_four_are_free() {
+        let cells = ["a1", "a2", "a3", "a4"]
+            .iter()
+            .map(|id| cell("19:00", Some(id)))
+            .collect::<Vec<_>>();
+        let candidates = candidates(&cells, &[time("19:00")]);
+        assert_eq!(ids(&candidates), vec!["a2", "a1", "a3", "a4"]);
+    }
+
+    #[test]
+    fn the_wanted_court_is_kept_over_a_fallback_won_with_it() {
+        let batch = vec![
+            (time("19:00"), "a1".to_string()),
+            (time("19:40"), "b1".to_string()),
+        ];
+        let bookings = vec![booking("b1", "19:40", 1), booking("a1", "19:00", 2)];
+        let (outcome, extras) = settle(
+            &parameters(),
+            NaiveDate::from_ymd(2024, 3, 20),
+            &batch,
+            &bookings,
+        )
+        .unwrap();
+        assert!(match outcome {
+            BookingOutcome::Booked { court_number, .. } => court_number == 2,
+            _ => false,
+        });
+        assert_eq!(ids_of(&extras), vec!["b1"]);
+    }
+
+    #[test]
+    fn a_fallback_won_alone_is_a_fall_back() {
+        let batch = vec![
+            (time("19:00"), "a1".to_string()),
+            (time("19:40"), "b1".to_string()),
+        ];
+        let bookings = vec![booking("b1", "19:40", 1)];
+        let date = NaiveDate::from_ymd(2024, 3, 20);
+        let (outcome, extras) = settle(&parameters(), date, &batch, &bookings).unwrap();
+        assert!(match outcome {
+            BookingOutcome::FellBack {
+                wanted_start,
+                start,
+                ..
+            } =>
+                wanted_start == club_time(date.and_time(time("19:00")))
+                    && start.time() == time("19:40"),
+            _ => false,
+        });
+        assert!(extras.is_empty());
+        assert!(settle(&parameters(), date, &batch, &[]).is_none());
+    }
+}
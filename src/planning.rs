@@ -0,0 +1,407 @@
+use crate::club::{club_time, Club};
+use crate::{court_times, fetch_bookings, fetch_planning, slot_time, Booking, Result};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveTime};
+use failure::bail;
+use schemars::JsonSchema;
+use select::document::Document;
+use select::node::Node;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::sync::Mutex;
+use std::thread;
+use std::time;
+
+/// Longest planning fetched at once, a bit more than the booking horizon.
+pub const MAX_PLANNING_DAYS: i64 = 31;
+const DEFAULT_CACHE_SECONDS: u64 = 30;
+/// Days of a range fetched concurrently, the governor wouldn't let more through anyway.
+const PLANNING_FETCH_THREADS: usize = 4;
+
+/// A court cell of the planning page, the courts of a time are its columns in order.
+#[derive(Debug, Clone)]
+pub struct PlanningCell {
+    pub time: NaiveTime,
+    pub free: bool,
+    /// books the court when free
+    pub slot_id: Option<String>,
+}
+
+fn is_cell(node: &Node) -> bool {
+    match node.attr("class") {
+        Some(class) => class.starts_with("creneau"),
+        None => false,
+    }
+}
+
+/// Every court cell of a planning page, in page order. Free cells are the
+/// `creneauLibre` ones, the other `creneau*` cells are taken.
+pub fn parse_planning(document: &Document) -> Vec<PlanningCell> {
+    document
+        .find(is_cell)
+        .filter_map(|node| {
+            let time = NaiveTime::parse_from_str(slot_time(&node)?.trim(), "%H:%M").ok()?;
+            let free = node.attr("class") == Some("creneauLibre");
+            let slot_id = match free {
+                true => node
+                    .attr("onclick")
+                    .and_then(|link| link.split("idTspl=").nth(1))
+                    .map(|id| id.replace("\"", "")),
+                false => None,
+            };
+            Some(PlanningCell {
+                time,
+                free,
+                slot_id,
+            })
+        })
+        .collect()
+}
+
+pub fn fetch_cells(client: &reqwest::Client, date: NaiveDate) -> Result<Vec<PlanningCell>> {
+    Ok(parse_planning(&fetch_planning(client, &date)?))
+}
+
+/// Court times with at least a free court, sorted.
+pub fn free_times(cells: &[PlanningCell]) -> Vec<NaiveTime> {
+    let mut times = cells
+        .iter()
+        .filter(|cell| cell.free)
+        .map(|cell| cell.time)
+        .collect::<Vec<_>>();
+    times.sort();
+    times.dedup();
+    times
+}
+
+pub fn free_slot_ids(cells: &[PlanningCell], time: NaiveTime) -> Vec<String> {
+    cells
+        .iter()
+        .filter(|cell| cell.free && cell.time == time)
+        .filter_map(|cell| cell.slot_id.clone())
+        .collect()
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<(String, NaiveDate), (time::Instant, Vec<PlanningCell>)>,
+    /// bumped by every invalidation, a planning fetched before is stale
+    generation: u64,
+}
+
+/// Plannings parsed in the last seconds, keyed by club and date. The booker fetches
+/// its planning directly, a stale one at opening time would cost the court.
+pub struct PlanningCache {
+    ttl: time::Duration,
+    state: Mutex<CacheState>,
+}
+
+impl PlanningCache {
+    pub fn new(ttl: time::Duration) -> Self {
+        PlanningCache {
+            ttl,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Keeps plannings `planning_cache_seconds`, 30 by default, 0 disables the cache.
+    pub fn from_env() -> Self {
+        let seconds = env::var("planning_cache_seconds")
+            .ok()
+            .and_then(|seconds| seconds.parse().ok())
+            .unwrap_or(DEFAULT_CACHE_SECONDS);
+        PlanningCache::new(time::Duration::from_secs(seconds))
+    }
+
+    fn key(date: NaiveDate) -> (String, NaiveDate) {
+        (Club::from_env().slug, date)
+    }
+
+    pub fn get(&self, date: NaiveDate) -> Option<Vec<PlanningCell>> {
+        let mut state = self.state.lock().unwrap();
+        let ttl = self.ttl;
+        state
+            .entries
+            .retain(|_, (fetched_at, _)| fetched_at.elapsed() < ttl);
+        state
+            .entries
+            .get(&PlanningCache::key(date))
+            .map(|(_, cells)| cells.clone())
+    }
+
+    /// To read before fetching a planning, for `insert`.
+    pub fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    /// Keeps a planning fetched at `generation`, unless a planning was invalidated since:
+    /// the fetch may have read the courts from before the change.
+    pub fn insert(&self, date: NaiveDate, cells: Vec<PlanningCell>, generation: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.generation == generation {
+            state
+                .entries
+                .insert(PlanningCache::key(date), (time::Instant::now(), cells));
+        }
+    }
+
+    pub fn cells(&self, client: &reqwest::Client, date: NaiveDate) -> Result<Vec<PlanningCell>> {
+        if let Some(cells) = self.get(date) {
+            return Ok(cells);
+        }
+        let generation = self.generation();
+        let cells = fetch_cells(client, date)?;
+        self.insert(date, cells.clone(), generation);
+        Ok(cells)
+    }
+
+    /// Forgets the planning of a date whose courts changed, e.g. after booking, and the
+    /// plannings being fetched.
+    pub fn invalidate(&self, date: NaiveDate) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.entries.remove(&PlanningCache::key(date));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CourtState {
+    Free,
+    Booked,
+    /// booked by the account of the proxy
+    Mine,
+    Closed,
+    /// the cells of the time couldn't be matched to their courts
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PlanningCourt {
+    pub court_number: u8,
+    pub state: CourtState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PlanningSlot {
+    pub start: DateTime<FixedOffset>,
+    pub courts: Vec<PlanningCourt>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PlanningDay {
+    pub date: NaiveDate,
+    pub slots: Vec<PlanningSlot>,
+}
+
+/// Day × time × court matrix of the club.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Planning {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub days: Vec<PlanningDay>,
+}
+
+/// Lays the cells of a day on the court time grid. The planning page doesn't number
+/// the courts, they're numbered after their column: the cells of a time are its courts
+/// in order. A time with fewer cells than the others can't tell which court is missing,
+/// its courts are `Unknown` rather than numbered wrongly, except the ones of the
+/// bookings. The page doesn't tell closed courts from taken ones either, a court is only
+/// `Closed` when its time has no cell at all.
+pub fn planning_day(date: NaiveDate, cells: &[PlanningCell], bookings: &[Booking]) -> PlanningDay {
+    let mut by_time = BTreeMap::new();
+    for cell in cells {
+        by_time.entry(cell.time).or_insert_with(Vec::new).push(cell);
+    }
+    let courts = by_time.values().map(Vec::len).max().unwrap_or(0);
+    let slots = court_times()
+        .into_iter()
+        .map(|time| {
+            let cells = by_time.get(&time).cloned().unwrap_or_default();
+            let laid_out = cells.is_empty() || cells.len() == courts;
+            let courts = (0..courts)
+                .map(|index| {
+                    let court_number = index as u8 + 1;
+                    let mine = bookings.iter().any(|booking| {
+                        booking.date() == date
+                            && booking.local_start().time() == time
+                            && booking.court_number == court_number
+                    });
+                    let (state, slot_id) = match cells.get(index) {
+                        _ if mine => (CourtState::Mine, None),
+                        _ if !laid_out => (CourtState::Unknown, None),
+                        Some(cell) if cell.free => (CourtState::Free, cell.slot_id.clone()),
+                        Some(_) => (CourtState::Booked, None),
+                        None => (CourtState::Closed, None),
+                    };
+                    PlanningCourt {
+                        court_number,
+                        state,
+                        slot_id,
+                    }
+                })
+                .collect();
+            PlanningSlot {
+                start: club_time(date.and_time(time)),
+                courts,
+            }
+        })
+        .collect();
+    PlanningDay { date, slots }
+}
+
+/// Fetches the planning of every day from `from` to `to` included, the days not found
+/// in the cache `PLANNING_FETCH_THREADS` at a time.
+pub fn fetch_planning_range(
+    client: &reqwest::Client,
+    cache: Option<&PlanningCache>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Planning> {
+    if to < from {
+        bail!("{} is before {}", to, from);
+    }
+    if to - from >= Duration::days(MAX_PLANNING_DAYS) {
+        bail!("at most {} days can be fetched at once", MAX_PLANNING_DAYS);
+    }
+    let mut dates = vec![from];
+    while *dates.last().unwrap() < to {
+        dates.push(dates.last().unwrap().succ());
+    }
+    let generation = cache.map(PlanningCache::generation);
+    let mut fetched = HashMap::new();
+    let missing = dates
+        .iter()
+        .cloned()
+        .filter(|date| match cache.and_then(|cache| cache.get(*date)) {
+            Some(cells) => {
+                fetched.insert(*date, cells);
+                false
+            }
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    for batch in missing.chunks(PLANNING_FETCH_THREADS) {
+        let fetches = batch
+            .iter()
+            .map(|date| {
+                let (client, date) = (client.clone(), *date);
+                thread::spawn(move || fetch_cells(&client, date))
+            })
+            .collect::<Vec<_>>();
+        for (date, fetching) in batch.iter().zip(fetches) {
+            let cells = match fetching.join() {
+                Ok(cells) => cells?,
+                Err(_) => bail!("fetching the planning of {} panicked", date),
+            };
+            if let (Some(cache), Some(generation)) = (cache, generation) {
+                cache.insert(*date, cells.clone(), generation);
+            }
+            fetched.insert(*date, cells);
+        }
+    }
+    let bookings = fetch_bookings(client)?;
+    let days = dates
+        .into_iter()
+        .map(|date| planning_day(date, &fetched[&date], &bookings))
+        .collect();
+    Ok(Planning { from, to, days })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(time: &str, free: bool) -> PlanningCell {
+        PlanningCell {
+            time: NaiveTime::parse_from_str(time, "%H:%M").unwrap(),
+            free,
+            slot_id: match free {
+                true => Some(format!("{}-free", time)),
+                false => None,
+            },
+        }
+    }
+
+    fn states(day: &PlanningDay, time: &str) -> Vec<CourtState> {
+        let time = NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+        day.slots
+            .iter()
+            .find(|slot| slot.start.time() == time)
+            .unwrap()
+            .courts
+            .iter()
+            .map(|court| court.state)
+            .collect()
+    }
+
+    #[test]
+    fn plannings_fetched_before_an_invalidation_are_not_cached() {
+        let cache = PlanningCache::new(time::Duration::from_secs(30));
+        let date = NaiveDate::from_ymd(2026, 11, 5);
+        let generation = cache.generation();
+        cache.invalidate(date);
+        cache.insert(date, vec![cell("19:00", true)], generation);
+        assert!(cache.get(date).is_none());
+        cache.insert(date, vec![cell("19:00", false)], cache.generation());
+        assert!(!cache.get(date).unwrap()[0].free);
+    }
+
+    #[test]
+    fn courts_are_numbered_after_their_column() {
+        let date = NaiveDate::from_ymd(2026, 11, 5);
+        let cells = vec![
+            cell("19:00", false),
+            cell("19:00", true),
+            cell("19:00", false),
+            cell("19:00", true),
+        ];
+        let bookings = vec![Booking {
+            id: "mine".to_string(),
+            start: club_time(date.and_hms(19, 0, 0)),
+            court_number: 3,
+        }];
+        let day = planning_day(date, &cells, &bookings);
+        assert_eq!(
+            states(&day, "19:00"),
+            vec![
+                CourtState::Booked,
+                CourtState::Free,
+                CourtState::Mine,
+                CourtState::Free
+            ]
+        );
+        let slot = day
+            .slots
+            .iter()
+            .find(|slot| slot.start.time() == NaiveTime::from_hms(19, 0, 0))
+            .unwrap();
+        assert_eq!(slot.courts[3].court_number, 4);
+        assert_eq!(slot.courts[3].slot_id, Some("19:00-free".to_string()));
+    }
+
+    #[test]
+    fn times_missing_cells_are_not_laid_out() {
+        let date = NaiveDate::from_ymd(2026, 11, 5);
+        let cells = vec![
+            cell("19:00", true),
+            cell("19:00", true),
+            cell("19:00", true),
+            cell("19:40", true),
+            cell("19:40", false),
+        ];
+        let bookings = vec![Booking {
+            id: "mine".to_string(),
+            start: club_time(date.and_hms(19, 40, 0)),
+            court_number: 2,
+        }];
+        let day = planning_day(date, &cells, &bookings);
+        assert_eq!(states(&day, "19:00"), vec![CourtState::Free; 3]);
+        assert_eq!(
+            states(&day, "19:40"),
+            vec![CourtState::Unknown, CourtState::Mine, CourtState::Unknown]
+        );
+        assert_eq!(states(&day, "20:20"), vec![CourtState::Closed; 3]);
+    }
+}
@@ -0,0 +1,375 @@
+use crate::booker::{self, notify, snipe, BookerParameters, Schedule, WatcherKind};
+use crate::club::to_club_time;
+use crate::notifier::{notifier_from_env, BookingOutcome, StdoutNotifier};
+use crate::opening::book_at_opening;
+use crate::store::{OutcomeStore, WatcherStore};
+use crate::watcher::Watcher;
+use crate::{get_credentials, session_client, session_headers, Result, WanaplayCredentials};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate};
+use futures::future::{self, Future, Loop};
+use futures::sync::oneshot;
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+use tokio::runtime::Runtime;
+use tokio::timer::Delay;
+
+/// Wanaplay sessions are renewed after this many minutes.
+const SESSION_MAX_AGE_MINUTES: u64 = 20;
+/// Longest a waiting watcher sleeps before reading the clock again, the timers don't
+/// count the time the machine was suspended.
+const MAX_WAIT_SECONDS: u64 = 60;
+const LOGIN_RETRY_SECONDS: u64 = 60;
+
+/// Authenticated client shared by every in-process watcher.
+pub struct Session {
+    credentials: WanaplayCredentials,
+    client: Mutex<Option<(reqwest::Client, HeaderMap, time::Instant)>>,
+}
+
+impl Session {
+    pub fn new(credentials: WanaplayCredentials) -> Self {
+        Session {
+            credentials,
+            client: Mutex::new(None),
+        }
+    }
+
+    fn logged(&self) -> Result<(reqwest::Client, HeaderMap)> {
+        let mut client = self.client.lock().unwrap();
+        if let Some((client, headers, logged_at)) = client.as_ref() {
+            if logged_at.elapsed() < time::Duration::from_secs(SESSION_MAX_AGE_MINUTES * 60) {
+                return Ok((client.clone(), headers.clone()));
+            }
+        }
+        let headers = session_headers(
+            self.credentials.login.clone(),
+            self.credentials.password.crypted(),
+        )?;
+        let fresh = session_client(headers.clone())?;
+        *client = Some((fresh.clone(), headers.clone(), time::Instant::now()));
+        Ok((fresh, headers))
+    }
+
+    pub fn client(&self) -> Result<reqwest::Client> {
+        Ok(self.logged()?.0)
+    }
+
+    /// Logs in again, so that the session can't expire at a critical moment.
+    pub fn renew(&self) -> Result<reqwest::Client> {
+        *self.client.lock().unwrap() = None;
+        self.client()
+    }
+
+    /// Async client of the same wanaplay session, to run requests concurrently.
+    pub fn async_client(&self) -> Result<reqwest::r#async::Client> {
+        Ok(reqwest::r#async::Client::builder()
+            .default_headers(self.logged()?.1)
+            .build()?)
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunState {
+    Running,
+    Attempting,
+    Stopped,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RuntimeStatus {
+    pub name: String,
+    pub state: RunState,
+    /// at the club
+    pub next_attempt: Option<DateTime<FixedOffset>>,
+    pub last_outcome: Option<BookingOutcome>,
+}
+
+struct Task {
+    status: Mutex<RuntimeStatus>,
+    parameters: Mutex<BookerParameters>,
+    /// wakes the watcher up while it waits, to read its new state or parameters
+    wake: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+impl Task {
+    fn is_stopped(&self) -> bool {
+        self.status.lock().unwrap().state == RunState::Stopped
+    }
+
+    fn wake(&self) {
+        if let Some(wake) = self.wake.lock().unwrap().take() {
+            let _ = wake.send(());
+        }
+    }
+
+    /// Resolves after `duration`, or before when woken up.
+    fn sleep(&self, duration: time::Duration) -> impl Future<Item = (), Error = ()> {
+        let (wake, woken) = oneshot::channel();
+        *self.wake.lock().unwrap() = Some(wake);
+        Delay::new(time::Instant::now() + duration)
+            .map_err(drop)
+            .select2(woken.map_err(drop))
+            .then(|_| Ok(()))
+    }
+}
+
+/// Runs watchers as tasks of one scheduler of the current process instead of docker
+/// services. A watcher waits on a timer of the scheduler, its attempts run on a thread
+/// of their own as they use the blocking client.
+pub struct WatcherRuntime {
+    session: Arc<Session>,
+    tasks: Mutex<HashMap<String, Arc<Task>>>,
+    scheduler: Mutex<Runtime>,
+}
+
+impl WatcherRuntime {
+    pub fn new(session: Session) -> Result<Self> {
+        Ok(WatcherRuntime {
+            session: Arc::new(session),
+            tasks: Mutex::new(HashMap::new()),
+            scheduler: Mutex::new(Runtime::new()?),
+        })
+    }
+
+    pub fn from_env() -> Result<Self> {
+        WatcherRuntime::new(Session::new(get_credentials()?))
+    }
+
+    /// Starts the watcher, or updates what it books when already running.
+    pub fn start(&self, watcher: &Watcher) {
+        let mut tasks = self.tasks.lock().unwrap();
+        if let Some(task) = tasks.get(&watcher.name) {
+            if !task.is_stopped() {
+                *task.parameters.lock().unwrap() = BookerParameters::from(watcher);
+                task.wake();
+                return;
+            }
+        }
+        let task = Arc::new(Task {
+            status: Mutex::new(RuntimeStatus {
+                name: watcher.name.clone(),
+                state: RunState::Running,
+                next_attempt: None,
+                last_outcome: None,
+            }),
+            parameters: Mutex::new(BookerParameters::from(watcher)),
+            wake: Mutex::new(None),
+        });
+        self.scheduler
+            .lock()
+            .unwrap()
+            .spawn(watch(self.session.clone(), task.clone()));
+        tasks.insert(watcher.name.clone(), task);
+    }
+
+    /// Stops the watcher, an attempt already running goes on until it ends.
+    pub fn stop(&self, name: &str) -> Option<RuntimeStatus> {
+        let task = self.tasks.lock().unwrap().remove(name)?;
+        let status = {
+            let mut status = task.status.lock().unwrap();
+            status.state = RunState::Stopped;
+            status.clone()
+        };
+        task.wake();
+        Some(status)
+    }
+
+    pub fn status(&self, name: &str) -> Option<RuntimeStatus> {
+        let tasks = self.tasks.lock().unwrap();
+        let status = tasks.get(name)?.status.lock().unwrap();
+        Some(status.clone())
+    }
+
+    pub fn statuses(&self) -> Vec<RuntimeStatus> {
+        self.tasks
+            .lock()
+            .unwrap()
+            .values()
+            .map(|task| task.status.lock().unwrap().clone())
+            .collect()
+    }
+}
+
+/// Disables a one-shot watcher in the store once done, so it isn't deployed again.
+fn deactivate(status: &Mutex<RuntimeStatus>) {
+    let name = {
+        let mut status = status.lock().unwrap();
+        status.state = RunState::Stopped;
+        status.next_attempt = None;
+        status.name.clone()
+    };
+    let store = WatcherStore::from_env();
+    let deactivated = store.get(&name).and_then(|watcher| match watcher {
+        Some(mut watcher) => {
+            watcher.enabled = false;
+            store.update(watcher).map(|_| ())
+        }
+        None => Ok(()),
+    });
+    if let Err(err) = deactivated {
+        eprintln!("unable to deactivate {}: {}", name, err);
+    }
+}
+
+/// How an attempt ended.
+enum Attempted {
+    /// the watcher was stopped or is done
+    Finished,
+    Date(NaiveDate),
+    /// logging in failed, to retry later
+    LoginFailed,
+}
+
+fn watch(session: Arc<Session>, task: Arc<Task>) -> impl Future<Item = (), Error = ()> {
+    future::loop_fn(None, move |last_target_date| {
+        next_step(&session, &task, last_target_date)
+    })
+}
+
+type Step = Box<dyn Future<Item = Loop<(), Option<NaiveDate>>, Error = ()> + Send>;
+
+/// Waits for the next attempt, or runs it on a thread once due.
+fn next_step(
+    session: &Arc<Session>,
+    task: &Arc<Task>,
+    last_target_date: Option<NaiveDate>,
+) -> Step {
+    if task.is_stopped() {
+        return Box::new(future::ok(Loop::Break(())));
+    }
+    let parameters = task.parameters.lock().unwrap().clone();
+    let now = Local::now();
+    let (next_attempt, target_date) = match booker::next_attempt(&parameters, now) {
+        Some(next) => next,
+        None => {
+            deactivate(&task.status);
+            return Box::new(future::ok(Loop::Break(())));
+        }
+    };
+    task.status.lock().unwrap().next_attempt = Some(to_club_time(&next_attempt));
+    if next_attempt > now || last_target_date == Some(target_date) {
+        let max_wait = time::Duration::from_secs(MAX_WAIT_SECONDS);
+        let wait = match (next_attempt - now).to_std() {
+            Ok(wait) if last_target_date != Some(target_date) => wait.min(max_wait),
+            _ => max_wait,
+        };
+        return Box::new(
+            task.sleep(wait)
+                .map(move |_| Loop::Continue(last_target_date)),
+        );
+    }
+    task.status.lock().unwrap().state = RunState::Attempting;
+    let (done, attempted) = oneshot::channel();
+    let (session, attempting) = (session.clone(), task.clone());
+    thread::spawn(move || {
+        let _ = done.send(attempt(&session, &attempting, &parameters, target_date));
+    });
+    let task = task.clone();
+    Box::new(attempted.map_err(drop).and_then(move |attempted| -> Step {
+        match attempted {
+            Attempted::Finished => Box::new(future::ok(Loop::Break(()))),
+            Attempted::Date(date) => Box::new(future::ok(Loop::Continue(Some(date)))),
+            Attempted::LoginFailed => Box::new(
+                task.sleep(time::Duration::from_secs(LOGIN_RETRY_SECONDS))
+                    .map(move |_| Loop::Continue(last_target_date)),
+            ),
+        }
+    }))
+}
+
+fn attempt(
+    session: &Session,
+    task: &Task,
+    parameters: &BookerParameters,
+    target_date: NaiveDate,
+) -> Attempted {
+    let status = &task.status;
+    let (outcome, attempted) = match parameters.kind {
+        WatcherKind::Sniper(sniper) => {
+            let stopped = || task.is_stopped();
+            (
+                snipe(session, parameters, &sniper, target_date, &stopped),
+                Attempted::Date(target_date),
+            )
+        }
+        WatcherKind::Opening => match book_at_opening(session, parameters, target_date) {
+            outcome @ BookingOutcome::LoginFailed { .. } => (outcome, Attempted::LoginFailed),
+            outcome => (outcome, Attempted::Date(target_date)),
+        },
+    };
+    if task.is_stopped() {
+        return Attempted::Finished;
+    }
+    let notified = match &status.lock().unwrap().last_outcome {
+        Some(BookingOutcome::LoginFailed { date, .. }) => *date == target_date,
+        _ => false,
+    };
+    if !notified {
+        match notifier_from_env() {
+            Ok(notifier) => notify(&notifier, outcome.clone()),
+            Err(err) => {
+                eprintln!("{}", err);
+                notify(&StdoutNotifier, outcome.clone());
+            }
+        }
+    }
+    let booked_once = match parameters.schedule {
+        Schedule::Once { .. } => outcome.is_booked(),
+        Schedule::Weekly { .. } => false,
+    };
+    let name = status.lock().unwrap().name.clone();
+    if let Err(err) = OutcomeStore::from_env().save(&name, &outcome) {
+        eprintln!("unable to record the outcome: {}", err);
+    }
+    {
+        let mut status = status.lock().unwrap();
+        status.last_outcome = Some(outcome);
+        if status.state == RunState::Attempting {
+            status.state = RunState::Running;
+        }
+    }
+    if booked_once {
+        deactivate(status);
+        return Attempted::Finished;
+    }
+    attempted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waking_a_task_ends_its_sleep() {
+        let watcher = serde_json::from_value::<Watcher>(json!({
+            "name": "thursday",
+            "court_time": "19:40",
+            "week_day": "Thursday",
+        }))
+        .unwrap();
+        let task = Arc::new(Task {
+            status: Mutex::new(RuntimeStatus {
+                name: watcher.name.clone(),
+                state: RunState::Running,
+                next_attempt: None,
+                last_outcome: None,
+            }),
+            parameters: Mutex::new(BookerParameters::from(&watcher)),
+            wake: Mutex::new(None),
+        });
+        let mut scheduler = Runtime::new().unwrap();
+        let (slept, sleeping) = oneshot::channel();
+        scheduler.spawn(
+            task.sleep(time::Duration::from_secs(3600))
+                .then(|_| slept.send(()).map_err(drop)),
+        );
+        task.wake();
+        let started = time::Instant::now();
+        scheduler.block_on(sleeping).unwrap();
+        assert!(started.elapsed() < time::Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,350 @@
+use crate::club::Club;
+use crate::{Booking, Result};
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Weekday};
+use itertools::Itertools;
+use std::collections::HashSet;
+use std::env;
+use std::str::FromStr;
+
+const DEFAULT_REMINDER_RULES: &str = "day_of@09:00,cancel_warning:2h";
+
+/// When to remind about bookings, parsed from `reminder_rules`:
+/// `day_of@09:00`, `evening_before@20:00`, `before:2h`, `digest:Mon@09:00`
+/// or `cancel_warning:2h` (2 hours before the club starts charging cancellations).
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReminderRule {
+    DayOf { at: NaiveTime },
+    EveningBefore { at: NaiveTime },
+    Before { lead: Duration },
+    WeeklyDigest { weekday: Weekday, at: NaiveTime },
+    LastChanceToCancel { lead: Duration },
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| format_err!("{} is not a valid time, expected HH:MM", time))
+}
+
+/// Parses durations such as `2h`, `45m` or `1h30m`.
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    for c in duration.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'd' | 'h' | 'm' if !number.is_empty() => {
+                let value = number.parse::<i64>()?;
+                total = total
+                    + match c {
+                        'd' => Duration::days(value),
+                        'h' => Duration::hours(value),
+                        _ => Duration::minutes(value),
+                    };
+                number.clear();
+            }
+            _ => bail!("{} is not a valid duration, expected e.g. 1h30m", duration),
+        }
+    }
+    if !number.is_empty() || total == Duration::zero() {
+        bail!("{} is not a valid duration, expected e.g. 1h30m", duration);
+    }
+    Ok(total)
+}
+
+impl FromStr for ReminderRule {
+    type Err = crate::Error;
+
+    fn from_str(rule: &str) -> Result<Self> {
+        let rule = rule.trim();
+        if rule.starts_with("day_of@") {
+            Ok(ReminderRule::DayOf {
+                at: parse_time(rule.trim_start_matches("day_of@"))?,
+            })
+        } else if rule.starts_with("evening_before@") {
+            Ok(ReminderRule::EveningBefore {
+                at: parse_time(rule.trim_start_matches("evening_before@"))?,
+            })
+        } else if rule.starts_with("before:") {
+            Ok(ReminderRule::Before {
+                lead: parse_duration(rule.trim_start_matches("before:"))?,
+            })
+        } else if rule.starts_with("cancel_warning:") {
+            Ok(ReminderRule::LastChanceToCancel {
+                lead: parse_duration(rule.trim_start_matches("cancel_warning:"))?,
+            })
+        } else if rule.starts_with("digest:") {
+            let mut parts = rule.trim_start_matches("digest:").splitn(2, '@');
+            match (parts.next(), parts.next()) {
+                (Some(weekday), Some(at)) => Ok(ReminderRule::WeeklyDigest {
+                    weekday: weekday
+                        .parse::<Weekday>()
+                        .map_err(|_| format_err!("{} is not a valid week day", weekday))?,
+                    at: parse_time(at)?,
+                }),
+                (_, _) => bail!(
+                    "{} is not a valid digest rule, expected digest:Mon@09:00",
+                    rule
+                ),
+            }
+        } else {
+            bail!("{} is not a valid reminder rule", rule)
+        }
+    }
+}
+
+pub fn reminder_rules_from_env() -> Result<Vec<ReminderRule>> {
+    env::var("reminder_rules")
+        .unwrap_or_else(|_| DEFAULT_REMINDER_RULES.to_string())
+        .split(',')
+        .filter(|rule| !rule.trim().is_empty())
+        .map(|rule| rule.parse())
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct Reminder {
+    pub at: NaiveDateTime,
+    pub rule: ReminderRule,
+    pub bookings: Vec<Booking>,
+}
+
+fn describe(booking: &Booking) -> String {
+    format!("{} (court {})", booking.court_time(), booking.court_number)
+}
+
+impl Reminder {
+    pub fn message(&self) -> String {
+        let details = self.bookings.iter().map(describe).join(" and ");
+        match &self.rule {
+            ReminderRule::DayOf { .. } => format!(
+                "{} bookings scheduled for today at {}",
+                self.bookings.len(),
+                details
+            ),
+            ReminderRule::EveningBefore { .. } => format!(
+                "{} bookings scheduled for tomorrow at {}",
+                self.bookings.len(),
+                details
+            ),
+            ReminderRule::Before { .. } => format!(
+                "squash {} at {}",
+                self.bookings[0].date().format("%a %d/%m"),
+                details
+            ),
+            ReminderRule::WeeklyDigest { .. } => format!(
+                "{} bookings this week:\n{}",
+                self.bookings.len(),
+                self.bookings
+                    .iter()
+                    .map(|booking| format!(
+                        "{} at {}",
+                        booking.date().format("%a %d/%m"),
+                        describe(booking)
+                    ))
+                    .join("\n")
+            ),
+            ReminderRule::LastChanceToCancel { .. } => format!(
+                "last chance to cancel for free {} at {}",
+                self.bookings[0].date().format("%a %d/%m"),
+                details
+            ),
+        }
+    }
+}
+
+fn next_weekday_at(after: NaiveDateTime, weekday: Weekday, at: NaiveTime) -> NaiveDateTime {
+    let mut date = after.date();
+    while date.weekday() != weekday || date.and_time(at) <= after {
+        date = date.succ();
+    }
+    date.and_time(at)
+}
+
+/// Every reminder firing strictly after `after` for the given bookings, sorted by instant.
+/// Rules reminding of the same bookings at the same instant remind once, with the first
+/// of them.
+pub fn upcoming_reminders(
+    rules: &[ReminderRule],
+    bookings: &[Booking],
+    after: NaiveDateTime,
+    club: &Club,
+) -> Vec<Reminder> {
+    let mut bookings = bookings.to_vec();
+    bookings.sort_by_key(Booking::local_start);
+    let mut reminders = vec![];
+    for rule in rules {
+        let instants = bookings
+            .iter()
+            .map(|booking| {
+                let at = match rule {
+                    ReminderRule::DayOf { at } => booking.date().and_time(*at),
+                    ReminderRule::EveningBefore { at } => booking.date().pred().and_time(*at),
+                    ReminderRule::Before { lead } => booking.local_start() - *lead,
+                    ReminderRule::WeeklyDigest { weekday, at } => {
+                        let week_before = booking.local_start() - Duration::days(7);
+                        next_weekday_at(week_before.max(after), *weekday, *at)
+                    }
+                    ReminderRule::LastChanceToCancel { lead } => {
+                        booking.local_start() - club.cancellation_notice - *lead
+                    }
+                };
+                (at, booking.clone())
+            })
+            .filter(|(at, booking)| *at > after && *at < booking.local_start());
+        for (at, group) in &instants.group_by(|(at, _)| *at) {
+            reminders.push(Reminder {
+                at,
+                rule: rule.clone(),
+                bookings: group.map(|(_, booking)| booking).collect(),
+            });
+        }
+    }
+    reminders.sort_by_key(|reminder| reminder.at);
+    let mut reminded = HashSet::new();
+    reminders.retain(|reminder| {
+        let ids = reminder.bookings.iter().map(|booking| booking.id.clone());
+        reminded.insert((reminder.at, ids.collect::<Vec<_>>()))
+    });
+    reminders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, NaiveDate, TimeZone};
+
+    fn club() -> Club {
+        Club {
+            slug: "club".to_string(),
+            name: "Club".to_string(),
+            address: "Paris".to_string(),
+            cancellation_notice: Duration::hours(24),
+        }
+    }
+
+    /// A booking on Wednesday 6 March 2024.
+    fn booking(id: &str, hour: u32, minute: u32) -> Booking {
+        Booking {
+            id: id.to_string(),
+            start: FixedOffset::east(3600)
+                .ymd(2024, 3, 6)
+                .and_hms(hour, minute, 0),
+            court_number: 1,
+        }
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2024, 3, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("2h").unwrap(), Duration::hours(2));
+        assert_eq!(parse_duration("45m").unwrap(), Duration::minutes(45));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::minutes(90));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::days(1));
+        for invalid in &["", "h", "2", "2x", "0m", "1h 30m"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn reminder_rules_are_parsed() {
+        let nine = NaiveTime::from_hms(9, 0, 0);
+        assert_eq!(
+            "day_of@09:00".parse::<ReminderRule>().unwrap(),
+            ReminderRule::DayOf { at: nine }
+        );
+        assert_eq!(
+            " evening_before@20:30".parse::<ReminderRule>().unwrap(),
+            ReminderRule::EveningBefore {
+                at: NaiveTime::from_hms(20, 30, 0)
+            }
+        );
+        assert_eq!(
+            "before:1h30m".parse::<ReminderRule>().unwrap(),
+            ReminderRule::Before {
+                lead: Duration::minutes(90)
+            }
+        );
+        assert_eq!(
+            "cancel_warning:2h".parse::<ReminderRule>().unwrap(),
+            ReminderRule::LastChanceToCancel {
+                lead: Duration::hours(2)
+            }
+        );
+        assert_eq!(
+            "digest:Mon@09:00".parse::<ReminderRule>().unwrap(),
+            ReminderRule::WeeklyDigest {
+                weekday: Weekday::Mon,
+                at: nine
+            }
+        );
+        for invalid in &[
+            "day_of@9h",
+            "before:",
+            "digest:Mon",
+            "digest:Funday@09:00",
+            "weekly@09:00",
+        ] {
+            assert!(invalid.parse::<ReminderRule>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn reminders_fire_strictly_after_the_last_check() {
+        let rules = vec![ReminderRule::DayOf {
+            at: NaiveTime::from_hms(9, 0, 0),
+        }];
+        let bookings = vec![booking("1", 19, 0)];
+        assert!(upcoming_reminders(&rules, &bookings, at(6, 9, 0), &club()).is_empty());
+        let reminders = upcoming_reminders(&rules, &bookings, at(6, 8, 59), &club());
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].at, at(6, 9, 0));
+    }
+
+    #[test]
+    fn bookings_reminded_together_are_grouped() {
+        let rules = vec![
+            ReminderRule::DayOf {
+                at: NaiveTime::from_hms(9, 0, 0),
+            },
+            ReminderRule::LastChanceToCancel {
+                lead: Duration::hours(2),
+            },
+        ];
+        let bookings = vec![booking("2", 19, 40), booking("1", 19, 0)];
+        let reminders = upcoming_reminders(&rules, &bookings, at(4, 0, 0), &club());
+        let summary = reminders
+            .iter()
+            .map(|reminder| {
+                let mut ids = reminder.bookings.iter().map(|booking| booking.id.as_str());
+                (reminder.at, reminder.rule.clone(), ids.join(","))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (at(5, 17, 0), rules[1].clone(), "1".to_string()),
+                (at(5, 17, 40), rules[1].clone(), "2".to_string()),
+                (at(6, 9, 0), rules[0].clone(), "1,2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rules_landing_together_remind_once() {
+        let rules = vec![
+            ReminderRule::Before {
+                lead: Duration::hours(10),
+            },
+            ReminderRule::DayOf {
+                at: NaiveTime::from_hms(9, 0, 0),
+            },
+        ];
+        let reminders = upcoming_reminders(&rules, &[booking("1", 19, 0)], at(4, 0, 0), &club());
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].at, at(6, 9, 0));
+        assert_eq!(reminders[0].rule, rules[0]);
+    }
+}
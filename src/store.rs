@@ -0,0 +1,271 @@
+use crate::notifier::BookingOutcome;
+use crate::watcher::{Compose, Watcher, WatcherStatus};
+use crate::Result;
+use chrono::Utc;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time;
+
+const DEFAULT_STORE_PATH: &str = "/data/watchers.json";
+const DEFAULT_OUTCOME_DIR: &str = "/data/outcomes";
+const LOCK_TIMEOUT_SECONDS: u64 = 10;
+
+/// Writes to a temporary file next to `path` then renames it, so readers never see a
+/// partially written file.
+pub(crate) fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoreContent {
+    next_id: u32,
+    watchers: Vec<Watcher>,
+}
+
+struct StoreLock {
+    path: PathBuf,
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// JSON file holding every watcher, shared by the proxy and the telegram bot.
+pub struct WatcherStore {
+    path: PathBuf,
+}
+
+impl WatcherStore {
+    pub fn new(path: PathBuf) -> Self {
+        WatcherStore { path }
+    }
+
+    pub fn from_env() -> Self {
+        WatcherStore::new(PathBuf::from(
+            env::var("watcher_store_path").unwrap_or_else(|_| DEFAULT_STORE_PATH.to_string()),
+        ))
+    }
+
+    fn lock(&self) -> Result<StoreLock> {
+        let path = self.path.with_extension("lock");
+        let deadline = time::Instant::now() + time::Duration::from_secs(LOCK_TIMEOUT_SECONDS);
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(StoreLock { path }),
+                Err(_) if time::Instant::now() < deadline => {
+                    thread::sleep(time::Duration::from_millis(50))
+                }
+                Err(err) => {
+                    // a lock older than the timeout was left by a crashed process
+                    let stale = fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .map_or(false, |age| age.as_secs() > LOCK_TIMEOUT_SECONDS);
+                    if !stale {
+                        bail!("unable to lock {:?}: {}", path, err);
+                    }
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+    }
+
+    fn read(&self) -> Result<StoreContent> {
+        if self.path.exists() {
+            return Ok(serde_json::from_reader(fs::File::open(&self.path)?)?);
+        }
+        // first start: import the watchers of the legacy compose file
+        let mut content = StoreContent::default();
+        if let Ok(path) = Compose::path_from_env() {
+            if path.exists() {
+                for mut watcher in Compose::load(&path)?.watchers() {
+                    content.next_id += 1;
+                    watcher.id = content.next_id;
+                    content.watchers.push(watcher);
+                }
+            }
+        }
+        Ok(content)
+    }
+
+    fn modify<T, F>(&self, modification: F) -> Result<T>
+    where
+        F: FnOnce(&mut StoreContent) -> Result<T>,
+    {
+        let _lock = self.lock()?;
+        let mut content = self.read()?;
+        let result = modification(&mut content)?;
+        write_atomically(
+            &self.path,
+            serde_json::to_string_pretty(&content)?.as_bytes(),
+        )?;
+        Ok(result)
+    }
+
+    pub fn list(&self) -> Result<Vec<Watcher>> {
+        Ok(self.read()?.watchers)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<Watcher>> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .find(|watcher| watcher.name == name))
+    }
+
+    pub fn insert(&self, mut watcher: Watcher) -> Result<Watcher> {
+        self.modify(|content| {
+            if content
+                .watchers
+                .iter()
+                .any(|other| other.name == watcher.name)
+            {
+                bail!("watcher already exists");
+            }
+            content.next_id += 1;
+            watcher.id = content.next_id;
+            watcher.created_at = Some(Utc::now());
+            watcher.updated_at = watcher.created_at;
+            content.watchers.push(watcher.clone());
+            Ok(watcher)
+        })
+    }
+
+    /// Replaces what the user sets of a watcher, what the server tracks is kept.
+    pub fn update(&self, mut watcher: Watcher) -> Result<Watcher> {
+        self.modify(|content| {
+            match content
+                .watchers
+                .iter_mut()
+                .find(|other| other.name == watcher.name)
+            {
+                Some(existing) => {
+                    watcher.id = existing.id;
+                    watcher.status = existing.status;
+                    watcher.created_at = existing.created_at;
+                    watcher.updated_at = Some(Utc::now());
+                    watcher.last_outcome = existing.last_outcome.take();
+                    watcher.next_attempt = existing.next_attempt;
+                    *existing = watcher.clone();
+                    Ok(watcher)
+                }
+                None => bail!("watcher {:?} not found", watcher.name),
+            }
+        })
+    }
+
+    pub fn set_status(&self, name: &str, status: WatcherStatus) -> Result<Watcher> {
+        self.modify(|content| {
+            match content
+                .watchers
+                .iter_mut()
+                .find(|watcher| watcher.name == name)
+            {
+                Some(watcher) => {
+                    watcher.status = status;
+                    watcher.updated_at = Some(Utc::now());
+                    Ok(watcher.clone())
+                }
+                None => bail!("watcher {:?} not found", name),
+            }
+        })
+    }
+
+    pub fn remove(&self, name: &str) -> Result<Watcher> {
+        self.modify(|content| {
+            match content
+                .watchers
+                .iter()
+                .position(|watcher| watcher.name == name)
+            {
+                Some(index) => Ok(content.watchers.remove(index)),
+                None => bail!("watcher {:?} not found", name),
+            }
+        })
+    }
+}
+
+/// Last booking outcome of every watcher, one JSON file per watcher in `outcome_dir`,
+/// `/data/outcomes` by default. The bookers write them wherever they run, the backends
+/// read them back, the directory has to be shared with the proxy.
+pub struct OutcomeStore {
+    dir: PathBuf,
+}
+
+impl OutcomeStore {
+    pub fn new(dir: PathBuf) -> Self {
+        OutcomeStore { dir }
+    }
+
+    pub fn from_env() -> Self {
+        OutcomeStore::new(PathBuf::from(
+            env::var("outcome_dir").unwrap_or_else(|_| DEFAULT_OUTCOME_DIR.to_string()),
+        ))
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+
+    pub fn save(&self, name: &str, outcome: &BookingOutcome) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        write_atomically(&self.path(name), serde_json::to_string(outcome)?.as_bytes())
+    }
+
+    /// `None` when the watcher never tried, or its outcome can't be read anymore.
+    pub fn load(&self, name: &str) -> Option<BookingOutcome> {
+        let file = fs::File::open(self.path(name)).ok()?;
+        serde_json::from_reader(file).ok()
+    }
+
+    pub fn remove(&self, name: &str) -> Result<()> {
+        match fs::remove_file(self.path(name)) {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            removed => Ok(removed?),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::env;
+    use std::fs;
+    use std::sync::Mutex;
+
+    lazy_static! {
+        static ref STORE_PATH: Mutex<u32> = Mutex::new(0);
+    }
+
+    /// Runs `test` on an empty store of its own, `watcher_store_path` is shared by the
+    /// tests so they take turns.
+    pub(crate) fn with_store<F: FnOnce()>(test: F) {
+        let mut count = STORE_PATH.lock().unwrap_or_else(|err| err.into_inner());
+        *count += 1;
+        let path = env::temp_dir().join(format!(
+            "wanaplay-watchers-{}-{}.json",
+            std::process::id(),
+            count
+        ));
+        env::set_var("watcher_store_path", &path);
+        env::set_var("outcome_dir", path.with_extension("outcomes"));
+        env::remove_var("compose_file_path");
+        test();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_dir_all(path.with_extension("outcomes"));
+    }
+}
@@ -0,0 +1,373 @@
+use crate::store::write_atomically;
+use crate::{credential_var, Result, WanaplayCredentials, WanaplayPassword};
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::scrypt::{scrypt, ScryptParams};
+use failure::bail;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const VAULT_VERSION: u32 = 1;
+const DEFAULT_ACCOUNT: &str = "default";
+const KEY_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 8;
+const TAG_LENGTH: usize = 16;
+/// scrypt cost, 2^15 iterations takes a fraction of a second and 32MB of memory.
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// Overwrites the bytes so the secret doesn't linger in freed memory.
+pub fn zeroize(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        // volatile so the compiler doesn't drop writes to memory about to be freed
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+}
+
+pub fn zeroize_string(secret: &mut String) {
+    // zeroes are valid UTF-8, the string stays valid
+    zeroize(unsafe { secret.as_bytes_mut() });
+}
+
+fn random_bytes(length: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; length];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    let hex = hex.trim();
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        bail!("{} is not valid hexadecimal", hex);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&hex[index..index + 2], 16)
+                .map_err(|_| format_err!("{} is not valid hexadecimal", hex))
+        })
+        .collect()
+}
+
+/// What unlocks the vault, a passphrase stretched with scrypt or a random key.
+pub enum VaultKey {
+    Passphrase(String),
+    /// 32 bytes, e.g. from `head -c 32 /dev/urandom | xxd -p -c 64`
+    Key(Vec<u8>),
+}
+
+impl Drop for VaultKey {
+    fn drop(&mut self) {
+        match self {
+            VaultKey::Passphrase(passphrase) => zeroize_string(passphrase),
+            VaultKey::Key(key) => zeroize(key),
+        }
+    }
+}
+
+impl VaultKey {
+    /// Reads the hex key of `vault_key_file`, or the `vault_passphrase`.
+    pub fn from_env() -> Result<Self> {
+        if let Ok(path) = env::var("vault_key_file") {
+            let mut hex = fs::read_to_string(&path)?;
+            let key = from_hex(&hex);
+            zeroize_string(&mut hex);
+            let key = key?;
+            if key.len() != KEY_LENGTH {
+                bail!("the key of {} should be {} bytes long", path, KEY_LENGTH);
+            }
+            return Ok(VaultKey::Key(key));
+        }
+        match credential_var("vault_passphrase") {
+            Ok(passphrase) => Ok(VaultKey::Passphrase(passphrase)),
+            Err(_) => {
+                bail!("environment variable vault_key_file or vault_passphrase should be set")
+            }
+        }
+    }
+
+    fn derive(&self, salt: &[u8]) -> Vec<u8> {
+        match self {
+            VaultKey::Key(key) => key.clone(),
+            VaultKey::Passphrase(passphrase) => {
+                let mut key = vec![0; KEY_LENGTH];
+                let params = ScryptParams::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P);
+                scrypt(passphrase.as_bytes(), salt, &params, &mut key);
+                key
+            }
+        }
+    }
+}
+
+/// Credentials of an account, the password can be kept as its SHA-1 digest only
+/// since it's all the site needs.
+#[derive(Serialize, Deserialize)]
+pub struct VaultAccount {
+    pub login: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_sha1: Option<String>,
+}
+
+impl Drop for VaultAccount {
+    fn drop(&mut self) {
+        for secret in self
+            .password
+            .iter_mut()
+            .chain(self.password_sha1.iter_mut())
+        {
+            zeroize_string(secret);
+        }
+    }
+}
+
+impl VaultAccount {
+    pub fn credentials(&self) -> Result<WanaplayCredentials> {
+        let password = match (&self.password, &self.password_sha1) {
+            (Some(password), _) => WanaplayPassword::Plain(password.clone()),
+            (None, Some(digest)) => WanaplayPassword::Sha1(digest.to_lowercase()),
+            (None, None) => bail!("account {} has no password", self.login),
+        };
+        Ok(WanaplayCredentials {
+            login: self.login.clone(),
+            password,
+        })
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct VaultContent {
+    accounts: BTreeMap<String, VaultAccount>,
+}
+
+/// What is written to disk, everything but the accounts is public.
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    tag: String,
+    ciphertext: String,
+}
+
+/// Wanaplay credentials of several accounts, encrypted with ChaCha20-Poly1305.
+pub struct Vault {
+    path: PathBuf,
+    key: VaultKey,
+    content: VaultContent,
+}
+
+impl Vault {
+    pub fn path_from_env() -> Option<PathBuf> {
+        env::var("vault_path").ok().map(PathBuf::from)
+    }
+
+    /// Opens the vault, an empty one if the file doesn't exist yet.
+    pub fn open(path: &Path, key: VaultKey) -> Result<Self> {
+        let content = match path.exists() {
+            true => Vault::decrypt(&serde_json::from_reader(fs::File::open(path)?)?, &key)?,
+            false => VaultContent::default(),
+        };
+        Ok(Vault {
+            path: path.to_path_buf(),
+            key,
+            content,
+        })
+    }
+
+    fn decrypt(file: &VaultFile, key: &VaultKey) -> Result<VaultContent> {
+        if file.version != VAULT_VERSION {
+            bail!("vault version {} is not supported", file.version);
+        }
+        let ciphertext = from_hex(&file.ciphertext)?;
+        let nonce = from_hex(&file.nonce)?;
+        let tag = from_hex(&file.tag)?;
+        if nonce.len() != NONCE_LENGTH || tag.len() != TAG_LENGTH {
+            bail!("the vault is corrupted");
+        }
+        let mut derived = key.derive(&from_hex(&file.salt)?);
+        let mut cipher = ChaCha20Poly1305::new(&derived, &nonce, &[]);
+        zeroize(&mut derived);
+        let mut plaintext = vec![0; ciphertext.len()];
+        if !cipher.decrypt(&ciphertext, &mut plaintext, &tag) {
+            bail!("unable to decrypt the vault, wrong passphrase or key");
+        }
+        let content = serde_json::from_slice(&plaintext);
+        zeroize(&mut plaintext);
+        Ok(content?)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut plaintext = serde_json::to_vec(&self.content)?;
+        let salt = random_bytes(SALT_LENGTH)?;
+        let nonce = random_bytes(NONCE_LENGTH)?;
+        let mut derived = self.key.derive(&salt);
+        let mut cipher = ChaCha20Poly1305::new(&derived, &nonce, &[]);
+        zeroize(&mut derived);
+        let mut ciphertext = vec![0; plaintext.len()];
+        let mut tag = vec![0; TAG_LENGTH];
+        cipher.encrypt(&plaintext, &mut ciphertext, &mut tag);
+        zeroize(&mut plaintext);
+        let file = VaultFile {
+            version: VAULT_VERSION,
+            salt: to_hex(&salt),
+            nonce: to_hex(&nonce),
+            tag: to_hex(&tag),
+            ciphertext: to_hex(&ciphertext),
+        };
+        write_atomically(&self.path, serde_json::to_string_pretty(&file)?.as_bytes())
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = (&String, &VaultAccount)> {
+        self.content.accounts.iter()
+    }
+
+    pub fn get(&self, account: &str) -> Option<&VaultAccount> {
+        self.content.accounts.get(account)
+    }
+
+    pub fn set(&mut self, account: &str, credentials: VaultAccount) {
+        self.content
+            .accounts
+            .insert(account.to_string(), credentials);
+    }
+
+    pub fn remove(&mut self, account: &str) -> Option<VaultAccount> {
+        self.content.accounts.remove(account)
+    }
+}
+
+/// Credentials decrypted from a vault, kept for the process since scrypt takes a
+/// fraction of a second and 32MB each time. Only the digest the site needs is kept.
+struct DecryptedCredentials {
+    path: PathBuf,
+    account: String,
+    /// of the vault file, decrypted again once changed
+    modified: SystemTime,
+    login: String,
+    password_sha1: String,
+}
+
+impl Drop for DecryptedCredentials {
+    fn drop(&mut self) {
+        zeroize_string(&mut self.password_sha1);
+    }
+}
+
+lazy_static! {
+    static ref DECRYPTED: Mutex<Option<DecryptedCredentials>> = Mutex::new(None);
+}
+
+/// Credentials of the `wanaplay_account` account (`default` when unset) of the
+/// vault at `vault_path`, `None` when no vault is configured. The vault is only
+/// decrypted again when its file changed.
+pub fn credentials_from_env() -> Result<Option<WanaplayCredentials>> {
+    let path = match Vault::path_from_env() {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let account = env::var("wanaplay_account").unwrap_or_else(|_| DEFAULT_ACCOUNT.to_string());
+    let modified = fs::metadata(&path)?.modified()?;
+    let mut decrypted = DECRYPTED.lock().unwrap();
+    let cached = match &*decrypted {
+        Some(decrypted) => {
+            decrypted.path == path && decrypted.account == account && decrypted.modified == modified
+        }
+        None => false,
+    };
+    if !cached {
+        let vault = Vault::open(&path, VaultKey::from_env()?)?;
+        let credentials = match vault.get(&account) {
+            Some(credentials) => credentials.credentials()?,
+            None => bail!("account {} not found in {}", account, path.display()),
+        };
+        *decrypted = Some(DecryptedCredentials {
+            path,
+            account,
+            modified,
+            login: credentials.login.clone(),
+            password_sha1: credentials.password.crypted(),
+        });
+    }
+    let decrypted = decrypted.as_ref().unwrap();
+    Ok(Some(WanaplayCredentials {
+        login: decrypted.login.clone(),
+        password: WanaplayPassword::Sha1(decrypted.password_sha1.clone()),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!(
+            "wanaplay-vault-{}-{}.json",
+            std::process::id(),
+            name
+        ))
+    }
+
+    fn key(byte: u8) -> VaultKey {
+        VaultKey::Key(vec![byte; KEY_LENGTH])
+    }
+
+    /// Saves a vault of one account at `path`, encrypted with `key(1)`.
+    fn save_vault(path: &Path) {
+        let mut vault = Vault::open(path, key(1)).unwrap();
+        vault.set(
+            DEFAULT_ACCOUNT,
+            VaultAccount {
+                login: "player@example.com".to_string(),
+                password: Some("secret".to_string()),
+                password_sha1: None,
+            },
+        );
+        vault.save().unwrap();
+    }
+
+    #[test]
+    fn accounts_are_decrypted_back() {
+        let path = vault_path("round-trip");
+        save_vault(&path);
+        let vault = Vault::open(&path, key(1)).unwrap();
+        let account = vault.get(DEFAULT_ACCOUNT).unwrap();
+        assert_eq!(account.login, "player@example.com");
+        assert_eq!(account.password.as_deref(), Some("secret"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_wrong_key_is_rejected() {
+        let path = vault_path("wrong-key");
+        save_vault(&path);
+        assert!(Vault::open(&path, key(2)).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_tampered_tag_is_rejected() {
+        let path = vault_path("tampered-tag");
+        save_vault(&path);
+        let mut file: VaultFile = serde_json::from_reader(fs::File::open(&path).unwrap()).unwrap();
+        let mut tag = from_hex(&file.tag).unwrap();
+        tag[0] ^= 1;
+        file.tag = to_hex(&tag);
+        fs::write(&path, serde_json::to_vec(&file).unwrap()).unwrap();
+        assert!(Vault::open(&path, key(1)).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
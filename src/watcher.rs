@@ -0,0 +1,638 @@
+use crate::backend::{booker_args, WatcherBackend};
+use crate::booker::{next_attempt, BookerParameters, Schedule, SkipPeriod, WatcherKind};
+use crate::club::to_club_time;
+use crate::notifier::{notifier_from_env, BookingOutcome, Event, EventKind, Notifier};
+use crate::store::{write_atomically, OutcomeStore, WatcherStore};
+use crate::{court_times, Result};
+use chrono::{DateTime, FixedOffset, Local, NaiveTime, Utc};
+use itertools::Itertools;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde_yaml::from_reader;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+const WANAPLAY_SERVICE_LABEL: &str = "wanaplay_type=bot";
+pub(crate) const WANAPLAY_STACK: &str = "wanaplay";
+const COMPOSE_VERSION: &str = "3.1";
+/// Docker names are at most 63 characters, once prefixed with the stack name.
+const MAX_NAME_LENGTH: usize = 63 - WANAPLAY_STACK.len() - 1;
+/// Docker secret holding a credential, mounted in `/run/secrets`.
+pub struct CredentialSecret {
+    pub name: &'static str,
+    /// variable giving the booker the path of the mounted secret
+    pub var: &'static str,
+    /// variable of the proxy giving the path of the same file, if it has it as a file
+    source_var: &'static str,
+}
+
+const LOGIN_SECRETS: [CredentialSecret; 2] = [
+    CredentialSecret {
+        name: "wanaplay_login",
+        var: "wanaplay_login_FILE",
+        source_var: "wanaplay_login_FILE",
+    },
+    CredentialSecret {
+        name: "wanaplay_password",
+        var: "wanaplay_password_FILE",
+        source_var: "wanaplay_password_FILE",
+    },
+];
+const VAULT_SECRETS: [CredentialSecret; 3] = [
+    CredentialSecret {
+        name: "wanaplay_vault",
+        var: "vault_path",
+        source_var: "vault_path",
+    },
+    CredentialSecret {
+        name: "wanaplay_vault_key",
+        var: "vault_key_file",
+        source_var: "vault_key_file",
+    },
+    CredentialSecret {
+        name: "wanaplay_vault_passphrase",
+        var: "vault_passphrase_FILE",
+        source_var: "vault_passphrase_FILE",
+    },
+];
+
+/// Secrets the watchers read their credentials from, like the proxy: the vault and its
+/// key file or passphrase when `vault_path` is set, the login and password otherwise.
+pub fn credential_secrets() -> Vec<&'static CredentialSecret> {
+    match (
+        env::var("vault_path").is_ok(),
+        env::var("vault_key_file").is_ok(),
+    ) {
+        (false, _) => LOGIN_SECRETS.iter().collect(),
+        (true, true) => vec![&VAULT_SECRETS[0], &VAULT_SECRETS[1]],
+        (true, false) => vec![&VAULT_SECRETS[0], &VAULT_SECRETS[2]],
+    }
+}
+/// Watchers are restarted when they crash, not when they're done.
+const WATCHER_RESTART_CONDITION: &str = "on-failure";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Service {
+    image: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    environment: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    volumes: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ports: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    labels: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secrets: Option<Vec<String>>,
+    /// restart policy of docker-compose
+    #[serde(skip_serializing_if = "Option::is_none")]
+    restart: Option<String>,
+    /// restart policy of docker swarm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deploy: Option<Deploy>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Deploy {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    restart_policy: Option<RestartPolicy>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RestartPolicy {
+    condition: String,
+}
+
+impl Service {
+    fn is_watcher(&self) -> bool {
+        match &self.labels {
+            Some(labels) => labels.iter().any(|label| label == WANAPLAY_SERVICE_LABEL),
+            None => false,
+        }
+    }
+}
+
+impl<'a> From<&'a Watcher> for Service {
+    fn from(watcher: &'a Watcher) -> Self {
+        Service {
+            image: "touplitoui/wanaplay-booker-bot".to_string(),
+            environment: Some(
+                credential_secrets()
+                    .iter()
+                    .map(|secret| format!("{}=/run/secrets/{}", secret.var, secret.name))
+                    .chain(
+                        ["wanaplay_account", "booking_partners"]
+                            .iter()
+                            .filter_map(|var| {
+                                env::var(var).ok().map(|value| format!("{}={}", var, value))
+                            }),
+                    )
+                    .collect(),
+            ),
+            command: Some(format!(
+                "wanaplay-booker {}",
+                booker_args(watcher).join(" ")
+            )),
+            // shares the outcomes with the proxy, e.g. `wanaplay_data:/data`
+            volumes: env::var("outcome_volume").ok().map(|volume| vec![volume]),
+            ports: None,
+            labels: Some(vec![WANAPLAY_SERVICE_LABEL.to_string()]),
+            secrets: Some(
+                credential_secrets()
+                    .iter()
+                    .map(|secret| secret.name.to_string())
+                    .collect(),
+            ),
+            // the booker exits once a one-shot watcher is done, it mustn't book again
+            restart: Some(WATCHER_RESTART_CONDITION.to_string()),
+            deploy: Some(Deploy {
+                restart_policy: Some(RestartPolicy {
+                    condition: WATCHER_RESTART_CONDITION.to_string(),
+                }),
+            }),
+        }
+    }
+}
+
+/// Secret of the compose file, created beforehand with `docker secret create` or read
+/// from a `file`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Secret {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    external: Option<bool>,
+}
+
+/// docker-compose file the watchers are exported to for `docker stack deploy`,
+/// services not created by the proxy are left untouched.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Compose {
+    version: String,
+    services: BTreeMap<String, Service>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    secrets: BTreeMap<String, Secret>,
+}
+
+impl Compose {
+    pub fn path_from_env() -> Result<PathBuf> {
+        match env::var("compose_file_path") {
+            Ok(path) => Ok(PathBuf::from(path)),
+            Err(_) => bail!("environment variable compose_file_path should be set"),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Compose {
+                version: COMPOSE_VERSION.to_string(),
+                services: BTreeMap::new(),
+                secrets: BTreeMap::new(),
+            });
+        }
+        Ok(from_reader(std::fs::File::open(path)?)?)
+    }
+
+    /// Watchers of a compose file written before the watcher store existed.
+    pub fn watchers(&self) -> Vec<Watcher> {
+        let re = Regex::new(r"wanaplay-booker -c (\d{2}:\d{2}):\d{2} -w (\w+)").unwrap();
+        self.services
+            .iter()
+            .filter(|(_, service)| service.is_watcher())
+            .filter_map(|(name, service)| {
+                let matches = re.captures(service.command.as_ref()?)?;
+                Some(Watcher {
+                    id: 0,
+                    name: name.clone(),
+                    status: WatcherStatus::Deployed,
+                    court_time: NaiveTime::parse_from_str(matches.get(1)?.as_str(), "%H:%M")
+                        .ok()?,
+                    fallback_times: vec![],
+                    schedule: Schedule::Weekly {
+                        week_day: matches.get(2)?.as_str().parse().ok()?,
+                    },
+                    kind: WatcherKind::Opening,
+                    enabled: true,
+                    skip: vec![],
+                    created_at: None,
+                    updated_at: None,
+                    last_outcome: None,
+                    next_attempt: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Replaces the watcher services of the compose file by the given watchers.
+    pub fn export(watchers: &[Watcher], secrets: &CredentialSecrets) -> Result<PathBuf> {
+        let path = Compose::path_from_env()?;
+        let mut compose = Compose::load(&path)?;
+        compose.services.retain(|_, service| !service.is_watcher());
+        compose.write(&path, watchers, secrets)?;
+        Ok(path)
+    }
+
+    /// Writes the service of one watcher, leaving the other services as they are.
+    pub fn update(watcher: &Watcher, secrets: &CredentialSecrets) -> Result<PathBuf> {
+        let path = Compose::path_from_env()?;
+        Compose::load(&path)?.write(&path, std::slice::from_ref(watcher), secrets)?;
+        Ok(path)
+    }
+
+    /// Sets the given watcher services, with the credential secrets they need.
+    fn write(
+        mut self,
+        path: &Path,
+        watchers: &[Watcher],
+        secrets: &CredentialSecrets,
+    ) -> Result<()> {
+        // secrets need 3.1, a newer version is kept
+        if is_older_version(&self.version, COMPOSE_VERSION) {
+            self.version = COMPOSE_VERSION.to_string();
+        }
+        let needed = credential_secrets();
+        // the secrets of the other credentials may not exist
+        self.secrets.retain(|name, _| {
+            needed.iter().any(|secret| secret.name == name)
+                || !LOGIN_SECRETS
+                    .iter()
+                    .chain(VAULT_SECRETS.iter())
+                    .any(|secret| secret.name == name)
+        });
+        for credential in needed {
+            let secret = self
+                .secrets
+                .entry(credential.name.to_string())
+                .or_insert_with(|| secrets.secret(credential));
+            // docker-compose rejects external secrets, a secret file set by hand is kept
+            if let (CredentialSecrets::Files(_), None) = (secrets, &secret.file) {
+                *secret = secrets.secret(credential);
+            }
+        }
+        for watcher in watchers {
+            self.services
+                .insert(watcher.name.clone(), Service::from(watcher));
+        }
+        write_atomically(path, serde_yaml::to_string(&self)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Where the watcher services read the credentials from.
+pub enum CredentialSecrets {
+    /// docker secrets created beforehand, only docker swarm has them
+    External,
+    /// files named after the secrets in a directory, relative to the compose file
+    Files(PathBuf),
+}
+
+impl CredentialSecrets {
+    /// Files of the `compose_secrets_dir` directory, `./secrets` by default.
+    pub fn files_from_env() -> Self {
+        CredentialSecrets::Files(PathBuf::from(
+            env::var("compose_secrets_dir").unwrap_or_else(|_| "./secrets".to_string()),
+        ))
+    }
+
+    /// The file of a secret is the one the proxy reads, e.g. its vault, or the file
+    /// named after the secret in the directory.
+    fn secret(&self, credential: &CredentialSecret) -> Secret {
+        match self {
+            CredentialSecrets::External => Secret {
+                file: None,
+                external: Some(true),
+            },
+            CredentialSecrets::Files(dir) => {
+                Secret {
+                    file: Some(env::var(credential.source_var).unwrap_or_else(|_| {
+                        dir.join(credential.name).to_string_lossy().to_string()
+                    })),
+                    external: None,
+                }
+            }
+        }
+    }
+}
+
+/// Whether the compose file `version` is older than `minimum`, an unreadable one is.
+fn is_older_version(version: &str, minimum: &str) -> bool {
+    let parse = |version: &str| {
+        version
+            .split('.')
+            .map(str::parse::<u32>)
+            .collect::<std::result::Result<Vec<_>, _>>()
+    };
+    match (parse(version), parse(minimum)) {
+        (Ok(version), Ok(minimum)) => version < minimum,
+        _ => true,
+    }
+}
+
+/// Lifecycle of a watcher: `Draft` until deployed, then whatever the backend reports.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WatcherStatus {
+    #[serde(alias = "Created")]
+    Draft,
+    Deployed,
+    Running,
+    WaitingForOpening,
+    Booked,
+    Failed,
+    Paused,
+    /// exited on its own, e.g. a one-shot watcher that booked
+    Done,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl Default for WatcherStatus {
+    fn default() -> Self {
+        WatcherStatus::Draft
+    }
+}
+
+impl WatcherStatus {
+    /// Status reached once a run ended with the given outcome.
+    pub fn after(outcome: &BookingOutcome) -> Self {
+        match outcome.is_booked() {
+            true => WatcherStatus::Booked,
+            false => WatcherStatus::Failed,
+        }
+    }
+
+    pub fn is_deployed(self) -> bool {
+        self != WatcherStatus::Draft
+    }
+}
+
+/// Books a court time on its schedule, a bot in the API.
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[schemars(rename = "Bot")]
+pub struct Watcher {
+    #[serde(default)]
+    #[schemars(skip_deserializing)]
+    pub id: u32,
+    #[schemars(regex(pattern = r"^[a-zA-Z0-9][a-zA-Z0-9_-]*$"))]
+    pub name: String,
+    #[serde(default)]
+    pub status: WatcherStatus,
+    #[serde(with = "crate::serde_court_time")]
+    #[schemars(schema_with = "crate::serde_court_time::schema")]
+    pub court_time: NaiveTime,
+    /// booked when the court time is taken, in this order
+    #[serde(default, with = "crate::serde_court_times")]
+    #[schemars(schema_with = "crate::serde_court_times::schema")]
+    pub fallback_times: Vec<NaiveTime>,
+    #[serde(flatten)]
+    pub schedule: Schedule,
+    #[serde(default)]
+    pub kind: WatcherKind,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub skip: Vec<SkipPeriod>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip_deserializing)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip_deserializing)]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_outcome: Option<BookingOutcome>,
+    /// at the club
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip_deserializing)]
+    pub next_attempt: Option<DateTime<FixedOffset>>,
+}
+
+impl Watcher {
+    /// Problems with the watcher fields, empty when it can be stored. The name ends up
+    /// in docker service and systemd unit names so it's restricted to what both accept.
+    pub fn validation_errors(&self) -> Vec<String> {
+        let mut errors = vec![];
+        let name = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9_-]*$").unwrap();
+        if !name.is_match(&self.name) || self.name.len() > MAX_NAME_LENGTH {
+            errors.push(format!(
+                "{:?} is not a valid name, expected up to {} letters, digits, - or _",
+                self.name, MAX_NAME_LENGTH
+            ));
+        }
+        let valid_times = court_times();
+        if !valid_times.contains(&self.court_time) {
+            errors.push(format!(
+                "{} is not a valid court time, should be one of {}",
+                self.court_time.format("%H:%M"),
+                valid_times
+                    .iter()
+                    .map(|time| time.format("%H:%M").to_string())
+                    .join(", ")
+            ));
+        }
+        for (index, fallback) in self.fallback_times.iter().enumerate() {
+            if !valid_times.contains(fallback)
+                || *fallback == self.court_time
+                || self.fallback_times[..index].contains(fallback)
+            {
+                errors.push(format!(
+                    "{} is not a valid fallback time, should be a court time other than {} and the other fallbacks",
+                    fallback.format("%H:%M"),
+                    self.court_time.format("%H:%M")
+                ));
+            }
+        }
+        if let Schedule::Once { date } = self.schedule {
+            if date < Local::today().naive_local() {
+                errors.push(format!("{} is in the past", date));
+            }
+        }
+        if let WatcherKind::Sniper(sniper) = self.kind {
+            if !self.fallback_times.is_empty() {
+                errors.push("a sniper can't have fallback times".to_string());
+            }
+            if !valid_times.contains(&sniper.until) || sniper.until < self.court_time {
+                errors.push(format!(
+                    "{} is not a valid sniping limit, should be a court time after {}",
+                    sniper.until.format("%H:%M"),
+                    self.court_time.format("%H:%M")
+                ));
+            }
+            if sniper.interval_seconds == 0 {
+                errors.push("interval_seconds should be positive".to_string());
+            }
+            if sniper.budget_minutes <= 0 {
+                errors.push("budget_minutes should be positive".to_string());
+            }
+        }
+        errors
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self.validation_errors().as_slice() {
+            [] => Ok(()),
+            errors => bail!("{}", errors.join(", ")),
+        }
+    }
+
+    /// Whether the watcher has nothing left to book, i.e. its one-shot date passed.
+    pub fn is_expired(&self) -> bool {
+        next_attempt(&BookerParameters::from(self), Local::now()).is_none()
+    }
+}
+
+fn with_backend_status(backend: &dyn WatcherBackend, mut watcher: Watcher) -> Watcher {
+    if !watcher.enabled {
+        watcher.status = WatcherStatus::Paused;
+    } else if let Some(status) = backend.status(&watcher) {
+        watcher.status = status;
+    }
+    if let Some(outcome) = backend.last_outcome(&watcher) {
+        watcher.last_outcome = Some(outcome);
+    }
+    watcher.next_attempt = match watcher.status {
+        WatcherStatus::Draft | WatcherStatus::Paused | WatcherStatus::Done => None,
+        _ => next_attempt(&BookerParameters::from(&watcher), Local::now())
+            .map(|(at, _)| to_club_time(&at)),
+    };
+    watcher
+}
+
+pub fn get_watchers(backend: &dyn WatcherBackend) -> Result<Vec<Watcher>> {
+    Ok(WatcherStore::from_env()
+        .list()?
+        .into_iter()
+        .map(|watcher| with_backend_status(backend, watcher))
+        .collect())
+}
+
+pub fn get_watcher(backend: &dyn WatcherBackend, name: &str) -> Result<Option<Watcher>> {
+    Ok(WatcherStore::from_env()
+        .get(name)?
+        .map(|watcher| with_backend_status(backend, watcher)))
+}
+
+pub fn add_watcher(watcher: Watcher) -> Result<Watcher> {
+    watcher.validate()?;
+    WatcherStore::from_env().insert(watcher)
+}
+
+/// Replaces the stored watcher of the same name, keeping its status. A deployed
+/// watcher is applied again so that it books with its new parameters.
+pub fn update_watcher(backend: &dyn WatcherBackend, watcher: Watcher) -> Result<Watcher> {
+    watcher.validate()?;
+    let watcher = WatcherStore::from_env().update(watcher)?;
+    apply(backend, &watcher)?;
+    Ok(with_backend_status(backend, watcher))
+}
+
+pub fn remove_watcher(backend: &dyn WatcherBackend, watcher: &Watcher) -> Result<()> {
+    if backend.status(watcher).is_some() {
+        backend.stop(watcher)?;
+    }
+    WatcherStore::from_env().remove(&watcher.name)?;
+    OutcomeStore::from_env().remove(&watcher.name)?;
+    Ok(())
+}
+
+/// Deploys every stored watcher on the backend and notifies the result.
+pub fn deploy_watchers(backend: &dyn WatcherBackend) -> Result<()> {
+    let store = WatcherStore::from_env();
+    let deployed = store.list().and_then(|watchers| {
+        let mut enabled = vec![];
+        for mut watcher in watchers {
+            if watcher.enabled && watcher.is_expired() {
+                watcher.enabled = false;
+                store.update(watcher)?;
+                continue;
+            }
+            if watcher.enabled {
+                enabled.push(watcher);
+            }
+        }
+        backend.deploy(&enabled)?;
+        for watcher in enabled {
+            if watcher.status == WatcherStatus::Draft {
+                store.set_status(&watcher.name, WatcherStatus::Deployed)?;
+            }
+        }
+        Ok(())
+    });
+    let event = match &deployed {
+        Ok(_) => Event::new(EventKind::BotDeployed, "bots deployed".to_string()),
+        Err(err) => Event::new(
+            EventKind::BotDeployFailed,
+            format!("unable to deploy bots: {}", err),
+        ),
+    };
+    if let Err(err) = notifier_from_env().and_then(|notifier| notifier.notify(&event)) {
+        eprintln!("{}", err);
+    }
+    deployed
+}
+
+/// Applies a changed watcher if it was already deployed: a paused watcher is stopped, an
+/// enabled one is started with its new arguments. The other watchers are left alone.
+fn apply(backend: &dyn WatcherBackend, watcher: &Watcher) -> Result<()> {
+    if watcher.status.is_deployed() {
+        match watcher.enabled {
+            true => backend.start(watcher)?,
+            false if backend.status(watcher).is_some() => backend.stop(watcher)?,
+            false => (),
+        }
+    }
+    Ok(())
+}
+
+/// Changes a stored watcher then applies it.
+fn modify_watcher<F>(backend: &dyn WatcherBackend, name: &str, modification: F) -> Result<Watcher>
+where
+    F: FnOnce(&mut Watcher) -> Result<()>,
+{
+    let store = WatcherStore::from_env();
+    let mut watcher = match store.get(name)? {
+        Some(watcher) => watcher,
+        None => bail!("watcher {} not found", name),
+    };
+    modification(&mut watcher)?;
+    let watcher = store.update(watcher)?;
+    apply(backend, &watcher)?;
+    Ok(with_backend_status(backend, watcher))
+}
+
+/// Stops booking until resumed, without losing the watcher.
+pub fn pause_watcher(backend: &dyn WatcherBackend, name: &str) -> Result<Watcher> {
+    modify_watcher(backend, name, |watcher| {
+        watcher.enabled = false;
+        Ok(())
+    })
+}
+
+pub fn resume_watcher(backend: &dyn WatcherBackend, name: &str) -> Result<Watcher> {
+    modify_watcher(backend, name, |watcher| {
+        watcher.enabled = true;
+        Ok(())
+    })
+}
+
+/// Doesn't book the dates of `period`, e.g. during holidays.
+pub fn skip_watcher(
+    backend: &dyn WatcherBackend,
+    name: &str,
+    period: SkipPeriod,
+) -> Result<Watcher> {
+    modify_watcher(backend, name, |watcher| {
+        if !watcher.skip.contains(&period) {
+            watcher.skip.push(period);
+            watcher.skip.sort_by_key(|period| period.from);
+        }
+        Ok(())
+    })
+}